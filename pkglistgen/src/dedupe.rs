@@ -0,0 +1,144 @@
+use crate::Package;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+/// Which attribute of a group of duplicates is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// The shortest attribute name, which is usually the unversioned one
+    Shortest,
+    /// The attribute with the highest version
+    Newest,
+    Alphabetical,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shortest" => Ok(Policy::Shortest),
+            "newest" => Ok(Policy::Newest),
+            "alphabetical" => Ok(Policy::Alphabetical),
+            _ => Err(format!(
+                "unknown policy `{s}`, expected shortest, newest or alphabetical"
+            )),
+        }
+    }
+}
+
+/// Compares versions like `nix-env` does: components are split on `.` and `-`, numbers are
+/// compared numerically and are newer than words, and `pre` is older than anything
+pub fn compareversions(a: &str, b: &str) -> Ordering {
+    let split = |v: &str| {
+        v.split(['.', '-'])
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+    };
+    let (a, b) = (split(a), split(b));
+    for i in 0..a.len().max(b.len()) {
+        let (x, y) = (a.get(i), b.get(i));
+        let o = match (x, y) {
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                (Ok(_), Err(_)) => Ordering::Greater,
+                (Err(_), Ok(_)) => Ordering::Less,
+                _ if x == "pre" && y != "pre" => Ordering::Less,
+                _ if y == "pre" && x != "pre" => Ordering::Greater,
+                _ => x.cmp(y),
+            },
+            (Some(x), None) if x == "pre" => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(y)) if y == "pre" => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (None, None) => Ordering::Equal,
+        };
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+    Ordering::Equal
+}
+
+fn find(parent: &mut [usize], i: usize) -> usize {
+    let mut r = i;
+    while parent[r] != r {
+        r = parent[r];
+    }
+    parent[i] = r;
+    r
+}
+
+/// Groups attributes that build the same package: identical out paths, or identical derivation
+/// names when out paths are unknown, and with `versions` also identical `pname`s. Returns the
+/// canonical attributes, sorted, and the aliases dropped for each of them.
+pub fn dedupe(
+    attrs: &[String],
+    packages: &HashMap<String, Package>,
+    versions: bool,
+    policy: Policy,
+) -> (Vec<String>, BTreeMap<String, Vec<String>>) {
+    let mut parent = (0..attrs.len()).collect::<Vec<_>>();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (i, a) in attrs.iter().enumerate() {
+        let p = &packages[a];
+        let mut keys = vec![];
+        match (p.outpath(), &p.name) {
+            (Some(o), _) => keys.push(format!("out:{o}")),
+            (None, Some(n)) => keys.push(format!("name:{n}")),
+            (None, None) => (),
+        }
+        if versions {
+            if let Some(pname) = &p.pname {
+                keys.push(format!("pname:{pname}"));
+            }
+        }
+        for k in keys {
+            match seen.get(&k) {
+                Some(&j) => {
+                    let (ri, rj) = (find(&mut parent, i), find(&mut parent, j));
+                    parent[ri] = rj;
+                }
+                None => {
+                    seen.insert(k, i);
+                }
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<&String>> = BTreeMap::new();
+    for (i, a) in attrs.iter().enumerate() {
+        let r = find(&mut parent, i);
+        groups.entry(r).or_default().push(a);
+    }
+
+    let mut kept = vec![];
+    let mut aliases = BTreeMap::new();
+    for (_, mut group) in groups {
+        group.sort_by(|a, b| {
+            let o = match policy {
+                Policy::Shortest => a.len().cmp(&b.len()),
+                Policy::Newest => {
+                    let v = |x: &String| packages[x].version.clone().unwrap_or_default();
+                    compareversions(&v(b), &v(a))
+                }
+                Policy::Alphabetical => Ordering::Equal,
+            };
+            o.then(a.cmp(b))
+        });
+        let canonical = group[0].to_string();
+        if group.len() > 1 {
+            aliases.insert(
+                canonical.clone(),
+                group[1..].iter().map(|a| a.to_string()).collect(),
+            );
+        }
+        kept.push(canonical);
+    }
+    kept.sort();
+    (kept, aliases)
+}
@@ -0,0 +1,138 @@
+use std::{fs, path::Path, process::Command};
+
+/// Configuration used when nixpkgs does not provide the one channels generate `packages.json` with
+const CONFIG: &str = "{ allowAliases = false; allowUnfree = true; }";
+
+/// Attributes passed to one `nix-instantiate` call
+const BATCH: usize = 500;
+
+/// Adds the arguments that select the nixpkgs checkout, system, config and overlays to `cmd`
+fn nixpkgsargs(
+    cmd: &mut Command,
+    nixpkgs: &str,
+    system: &str,
+    overlays: &[String],
+) -> Result<(), String> {
+    let channelconfig = format!("{nixpkgs}/pkgs/top-level/packages-config.nix");
+    let config = if Path::new(&channelconfig).is_file() {
+        format!("import {}", absolute(&channelconfig)?)
+    } else {
+        CONFIG.to_string()
+    };
+
+    cmd.arg("--argstr")
+        .arg("system")
+        .arg(system)
+        .arg("--arg")
+        .arg("config")
+        .arg(config);
+    if !overlays.is_empty() {
+        let imports = overlays
+            .iter()
+            .map(|o| absolute(o).map(|o| format!("(import {o})")))
+            .collect::<Result<Vec<_>, _>>()?;
+        cmd.arg("--arg")
+            .arg("overlays")
+            .arg(format!("[ {} ]", imports.join(" ")));
+    }
+    Ok(())
+}
+
+/// Evaluates `nixpkgs` with `nix-env` and returns the attributes in the format of the `packages` map
+/// of `packages.json`. Unfree packages are included so license filters work the same as for
+/// channel data. With `drvpaths`, the derivation paths are recorded, but `nix-env` does not write
+/// the derivations themselves, see [`instantiate`]. `outpaths` records out paths.
+pub fn evaluate(
+    nixpkgs: &str,
+    system: &str,
+    overlays: &[String],
+    drvpaths: bool,
+    outpaths: bool,
+) -> Result<String, String> {
+    let mut cmd = Command::new("nix-env");
+    cmd.arg("-f").arg(nixpkgs);
+    nixpkgsargs(&mut cmd, nixpkgs, system, overlays)?;
+    cmd.arg("-qaP").arg("--json").arg("--meta");
+    if drvpaths {
+        cmd.arg("--drv-path");
+    }
+    if outpaths {
+        cmd.arg("--out-path");
+    }
+
+    match cmd.output() {
+        Ok(o) if o.status.success() => {
+            String::from_utf8(o.stdout).map_err(|e| format!("nix-env: {e}"))
+        }
+        Ok(o) => Err(format!(
+            "nix-env: {}",
+            String::from_utf8_lossy(&o.stderr).trim()
+        )),
+        Err(e) => Err(format!("nix-env: {e}")),
+    }
+}
+
+fn instantiatebatch(
+    nixpkgs: &str,
+    system: &str,
+    overlays: &[String],
+    attrs: &[String],
+) -> Result<(), String> {
+    let mut cmd = Command::new("nix-instantiate");
+    cmd.arg(nixpkgs);
+    nixpkgsargs(&mut cmd, nixpkgs, system, overlays)?;
+    for a in attrs {
+        cmd.arg("-A").arg(a);
+    }
+    match cmd.output() {
+        Ok(o) if o.status.success() => Ok(()),
+        Ok(o) => Err(format!(
+            "nix-instantiate: {}",
+            String::from_utf8_lossy(&o.stderr).trim()
+        )),
+        Err(e) => Err(format!("nix-instantiate: {e}")),
+    }
+}
+
+/// Instantiates `attrs` at once, splitting them in halves on failure until the failing attributes
+/// are found, so a single broken package costs a few extra calls instead of one per attribute
+fn bisect(
+    nixpkgs: &str,
+    system: &str,
+    overlays: &[String],
+    attrs: &[String],
+    failed: &mut Vec<(String, String)>,
+) {
+    match instantiatebatch(nixpkgs, system, overlays, attrs) {
+        Ok(()) => (),
+        Err(e) if attrs.len() == 1 => failed.push((attrs[0].clone(), e)),
+        Err(_) => {
+            let (a, b) = attrs.split_at(attrs.len() / 2);
+            bisect(nixpkgs, system, overlays, a, failed);
+            bisect(nixpkgs, system, overlays, b, failed);
+        }
+    }
+}
+
+/// Writes the derivations of `attrs` to the store with `nix-instantiate`, so their build inputs can
+/// be read from the paths `evaluate` recorded. Failing batches are bisected, and the attributes
+/// that fail on their own are returned with their error.
+pub fn instantiate(
+    nixpkgs: &str,
+    system: &str,
+    overlays: &[String],
+    attrs: &[String],
+) -> Vec<(String, String)> {
+    let mut failed = vec![];
+    for batch in attrs.chunks(BATCH) {
+        bisect(nixpkgs, system, overlays, batch, &mut failed);
+    }
+    failed
+}
+
+/// Paths in Nix expressions have to be absolute
+fn absolute(path: &str) -> Result<String, String> {
+    fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().to_string())
+        .map_err(|e| format!("{path}: {e}"))
+}
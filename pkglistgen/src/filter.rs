@@ -0,0 +1,194 @@
+use crate::{rules::globregex, Meta};
+use regex::Regex;
+use serde_json::Value;
+use std::fmt;
+
+/// Keys that can be tested in a filter expression, with whether they take a value
+const KEYS: [(&str, bool); 12] = [
+    ("platform", true),
+    ("badplatform", true),
+    ("hydra", false),
+    ("license", true),
+    ("maintainer", true),
+    ("team", true),
+    ("available", false),
+    ("free", false),
+    ("unfree", false),
+    ("broken", false),
+    ("insecure", false),
+    ("unsupported", false),
+];
+
+/// A boolean expression over package metadata, such as
+/// `platform:aarch64-linux and (license:GPL* or team:gnome) and not broken`
+#[derive(Debug)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// A key with an optional pattern, where `*` and `?` are wildcards. `hydra` also takes a
+    /// platform to test for a specific one.
+    Atom(String, Option<Regex>, String),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::And(a, b) => write!(f, "({a} and {b})"),
+            Expr::Or(a, b) => write!(f, "({a} or {b})"),
+            Expr::Not(a) => write!(f, "not {a}"),
+            Expr::Atom(k, None, _) => write!(f, "{k}"),
+            Expr::Atom(k, Some(_), v) => write!(f, "{k}:{v}"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Vec<String> {
+    s.replace('(', " ( ")
+        .replace(')', " ) ")
+        .replace('!', " ! ")
+        .split_whitespace()
+        .map(|t| t.to_string())
+        .collect()
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut e = self.and()?;
+        while matches!(self.peek(), Some("or" | "||")) {
+            self.next();
+            e = Expr::Or(Box::new(e), Box::new(self.and()?));
+        }
+        Ok(e)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut e = self.unary()?;
+        while matches!(self.peek(), Some("and" | "&&")) {
+            self.next();
+            e = Expr::And(Box::new(e), Box::new(self.unary()?));
+        }
+        Ok(e)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next().as_deref() {
+            Some("not" | "!") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some("(") => {
+                let e = self.or()?;
+                match self.next().as_deref() {
+                    Some(")") => Ok(e),
+                    _ => Err("expected `)`".to_string()),
+                }
+            }
+            Some(t) if !matches!(t, ")" | "and" | "or" | "&&" | "||") => atom(t),
+            Some(t) => Err(format!("unexpected `{t}`")),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn atom(t: &str) -> Result<Expr, String> {
+    let (key, value) = match t.split_once(':') {
+        Some((k, v)) => (k.to_lowercase(), Some(v)),
+        None => (t.to_lowercase(), None),
+    };
+    let takesvalue = match KEYS.iter().find(|(k, _)| *k == key) {
+        Some((_, v)) => *v,
+        None => return Err(format!("unknown filter `{key}`")),
+    };
+    match value {
+        None if takesvalue => Err(format!("`{key}` needs a value, as in `{key}:VALUE`")),
+        Some(_) if !takesvalue && key != "hydra" => Err(format!("`{key}` does not take a value")),
+        None => Ok(Expr::Atom(key, None, String::new())),
+        Some(v) => {
+            let r = Regex::new(&format!("(?i){}", globregex(v))).map_err(|e| e.to_string())?;
+            Ok(Expr::Atom(key, Some(r), v.to_string()))
+        }
+    }
+}
+
+pub fn parse(s: &str) -> Result<Expr, String> {
+    let mut p = Parser {
+        tokens: tokenize(s),
+        pos: 0,
+    };
+    let e = p.or().map_err(|e| format!("filter: {e}"))?;
+    match p.peek() {
+        None => Ok(e),
+        Some(t) => Err(format!("filter: unexpected `{t}`")),
+    }
+}
+
+/// Strings of a value that is either a single item or a list of items. Attribute sets are
+/// represented by all of `fields` they have, so a pattern matching any of them matches the set.
+fn strings(v: Option<&Value>, fields: &[&str]) -> Vec<String> {
+    let items = match v {
+        Some(Value::Array(a)) => a.iter().collect(),
+        Some(v) => vec![v],
+        None => vec![],
+    };
+    items
+        .into_iter()
+        .flat_map(|i| match i {
+            Value::String(s) => vec![s.clone()],
+            Value::Object(o) => fields
+                .iter()
+                .filter_map(|f| o.get(*f).and_then(|v| v.as_str()).map(|s| s.to_string()))
+                .collect(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+impl Expr {
+    pub fn eval(&self, meta: &Meta) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(meta) && b.eval(meta),
+            Expr::Or(a, b) => a.eval(meta) || b.eval(meta),
+            Expr::Not(a) => !a.eval(meta),
+            Expr::Atom(key, pattern, _) => {
+                let any = |values: Vec<String>| match pattern {
+                    Some(p) => values.iter().any(|v| p.is_match(v)),
+                    None => !values.is_empty(),
+                };
+                let platforms = strings(meta.platforms.as_ref(), &[]);
+                let bad = strings(meta.badplatforms.as_ref(), &[]);
+                match key.as_str() {
+                    "platform" => any(platforms.into_iter().filter(|p| !bad.contains(p)).collect()),
+                    "badplatform" => any(bad),
+                    // Hydra builds the supported platforms unless hydraPlatforms says otherwise
+                    "hydra" => any(match &meta.hydraplatforms {
+                        Some(h) => strings(Some(h), &[]),
+                        None => platforms.into_iter().filter(|p| !bad.contains(p)).collect(),
+                    }),
+                    "license" => any(strings(meta.license.as_ref(), &["spdxId", "shortName"])),
+                    "maintainer" => any(strings(meta.maintainers.as_ref(), &["github", "name", "email"])),
+                    "team" => any(strings(meta.teams.as_ref(), &["shortName", "scope"])),
+                    "available" => meta.available != Some(false),
+                    "free" => meta.unfree != Some(true),
+                    "unfree" => meta.unfree == Some(true),
+                    "broken" => meta.broken == Some(true),
+                    "insecure" => meta.insecure == Some(true),
+                    "unsupported" => meta.unsupported == Some(true),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
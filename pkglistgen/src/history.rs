@@ -0,0 +1,42 @@
+use serde::Deserialize;
+use std::{collections::HashMap, fs};
+
+#[derive(Deserialize, Debug)]
+pub struct PkgResult {
+    pub status: String,
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// Processing time, missing in reports of older generator versions
+    pub seconds: Option<f64>,
+}
+
+/// The `report.json` written by a previous run of the generator
+#[derive(Deserialize, Debug, Default)]
+pub struct History {
+    pub packages: HashMap<String, PkgResult>,
+}
+
+impl History {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        serde_json::from_str(&data).map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// Whether `attr` produced components last time, `None` if that is unknown because the package
+    /// was not processed or failed to build
+    pub fn produced(&self, attr: &str) -> Option<bool> {
+        let r = self.packages.get(attr)?;
+        if !r.components.is_empty() {
+            Some(true)
+        } else if r.status == "no-metadata" {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// How long processing `attr` took last time
+    pub fn seconds(&self, attr: &str) -> Option<f64> {
+        self.packages.get(attr)?.seconds
+    }
+}
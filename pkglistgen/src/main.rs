@@ -1,7 +1,26 @@
 use clap::{ArgGroup, Parser};
 use owo_colors::{OwoColorize, Stream::Stdout};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::Path};
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+use serde_json::Value;
+use dedupe::{dedupe, Policy};
+use filter::Expr;
+use history::History;
+use predict::predict;
+use rules::Rules;
+use shard::shard;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+mod dedupe;
+mod eval;
+mod filter;
+mod history;
+mod predict;
+mod rules;
+mod shard;
 
 /// Generate Appstream data for a given package
 #[derive(Parser, Debug)]
@@ -27,6 +46,11 @@ struct Args {
     #[clap(short, long)]
     output: Option<String>,
 
+    /// Split the list into this many files OUTPUT.0 to OUTPUT.N-1 with about the same processing time, estimated from
+    /// --history
+    #[clap(long, requires = "output")]
+    shards: Option<usize>,
+
     /// Include ALL packages (including kernels, drivers, etc.) NOT RECOMMENDED
     #[clap(short, long)]
     everything: bool,
@@ -34,6 +58,63 @@ struct Args {
     /// packages.json file location
     #[clap(short, long)]
     packages: Option<String>,
+
+    /// Evaluate this nixpkgs checkout with nix-env instead of reading packages.json
+    #[clap(short, long, conflicts_with = "packages")]
+    nixpkgs: Option<String>,
+
+    /// Overlay to apply when evaluating nixpkgs, can be repeated
+    #[clap(long, requires = "nixpkgs")]
+    overlay: Vec<String>,
+
+    /// Save the evaluated packages in the packages.json format to this file
+    #[clap(long, requires = "nixpkgs")]
+    save_packages: Option<String>,
+
+    /// Exclusion rules file, replacing the built-in rules
+    #[clap(short = 'x', long)]
+    exclude: Option<String>,
+
+    /// List only packages whose meta matches this expression, e.g. 'platform:aarch64-linux and (license:GPL* or
+    /// team:gnome) and not maintainer:alice'
+    #[clap(long)]
+    filter: Option<String>,
+
+    /// Print which filter or exclusion rule drops the given attribute
+    #[clap(long)]
+    explain: Option<String>,
+
+    /// List only packages likely to have AppStream metadata, most likely first
+    #[clap(long)]
+    likely_metadata: bool,
+
+    /// Keep only one attribute of those with identical out paths, or identical names when out paths are unknown
+    #[clap(long)]
+    dedupe: bool,
+
+    /// Also keep only one attribute of versioned variants with the same pname, implies --dedupe
+    #[clap(long)]
+    dedupe_versions: bool,
+
+    /// Which duplicate to keep: shortest, newest or alphabetical
+    #[clap(long, default_value = "shortest")]
+    canonical: Policy,
+
+    /// Write the dropped duplicates of each kept attribute as JSON to this file
+    #[clap(long)]
+    aliases: Option<String>,
+
+    /// report.json of a previous generator run, used to predict metadata
+    #[clap(long)]
+    history: Option<String>,
+
+    /// List only packages that produced metadata in the --history run, and new packages if --previous is given
+    #[clap(long, requires = "history")]
+    incremental: bool,
+
+    /// packages.json the --history run was generated from, to find packages added since
+    #[clap(long, requires = "incremental")]
+    previous: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -41,22 +122,93 @@ struct PackageBase {
     packages: HashMap<String, Package>,
 }
 
+/// Only the attribute names of a packages.json
+#[derive(Deserialize, Debug)]
+struct AttrBase {
+    packages: HashMap<String, IgnoredAny>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Package {
     system: String,
     meta: Meta,
+    name: Option<String>,
+    pname: Option<String>,
+    version: Option<String>,
+    /// Out paths are only present in local evaluations or when queried explicitly
+    outputs: Option<HashMap<String, Option<String>>>,
+    /// Only present in local evaluations
+    #[serde(rename = "drvPath")]
+    drvpath: Option<String>,
+}
+impl Package {
+    fn outpath(&self) -> Option<&String> {
+        self.outputs.as_ref()?.get("out")?.as_ref()
+    }
 }
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Meta {
     broken: Option<bool>,
     insecure: Option<bool>,
     unsupported: Option<bool>,
     unfree: Option<bool>,
+    #[serde(rename = "mainProgram")]
+    mainprogram: Option<String>,
+    available: Option<bool>,
+    platforms: Option<Value>,
+    #[serde(rename = "badPlatforms")]
+    badplatforms: Option<Value>,
+    #[serde(rename = "hydraPlatforms")]
+    hydraplatforms: Option<Value>,
+    license: Option<Value>,
+    maintainers: Option<Value>,
+    teams: Option<Value>,
+}
+
+/// Everything that decides whether an attribute is listed
+struct Filters<'a> {
+    args: &'a Args,
+    arch: String,
+    rules: Option<Rules>,
+    expr: Option<Expr>,
+    history: Option<History>,
+    /// Attributes of the previous packages.json
+    previous: Option<HashSet<String>>,
 }
 
 fn main() {
     let args = Args::parse();
-    let inputdata = if let Some(path) = args.packages {
+    let arch = if let Some(a) = &args.arch {
+        a.to_string()
+    } else {
+        "x86_64-linux".to_string()
+    };
+    if args.shards == Some(0) {
+        eprintln!("{} --shards must be at least 1", "error:".if_supports_color(Stdout, |x| x.red()));
+        std::process::exit(1);
+    }
+
+    let inputdata = if let Some(nixpkgs) = &args.nixpkgs {
+        let packages = match eval::evaluate(
+            nixpkgs,
+            &arch,
+            &args.overlay,
+            args.likely_metadata,
+            args.dedupe || args.dedupe_versions,
+        ) {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("{} {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+                std::process::exit(1);
+            }
+        };
+        let inputdata = format!("{{\"version\": 2, \"packages\": {packages}}}");
+        if let Some(savepath) = &args.save_packages {
+            fs::write(savepath, &inputdata).expect("Failed to write to file");
+        }
+        inputdata
+    } else if let Some(path) = &args.packages {
         if Path::new(&path).is_file() {
             fs::read_to_string(path).expect("Failed to read json file")
         } else {
@@ -70,175 +222,182 @@ fn main() {
     };
     let data: PackageBase = serde_json::from_str(&inputdata).expect("Failed to parse json file");
 
-    let arch = if let Some(a) = args.arch {
-        a
+    let rules = if args.everything {
+        None
+    } else if let Some(path) = &args.exclude {
+        Some(Rules::load(path))
     } else {
-        "x86_64-linux".to_string()
+        Some(Rules::parse("exclude.conf", rules::DEFAULT))
+    };
+    let rules = match rules.transpose() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{} {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+            std::process::exit(1);
+        }
+    };
+
+    let history = match args.history.as_deref().map(History::load).transpose() {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("{} {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+            std::process::exit(1);
+        }
     };
 
-    let mut pkgdata = data.packages;
+    let previous = args.previous.as_ref().map(|path| {
+        let inputdata = fs::read_to_string(path).expect("Failed to read json file");
+        let attrs: AttrBase = serde_json::from_str(&inputdata).expect("Failed to parse json file");
+        attrs.packages.into_keys().collect::<HashSet<_>>()
+    });
 
-    // Filter by license
-    if args.free {
-        pkgdata = pkgdata
-            .into_iter()
-            .filter(|(_, p)| p.meta.unfree != Some(true))
-            .collect::<HashMap<_, _>>();
-    } else if args.unfree {
-        pkgdata = pkgdata
-            .into_iter()
-            .filter(|(_, p)| p.meta.unfree == Some(true))
-            .collect::<HashMap<_, _>>();
+    let expr = match args.filter.as_deref().map(filter::parse).transpose() {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("{} {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+            std::process::exit(1);
+        }
+    };
+
+    let filters = Filters {
+        args: &args,
+        arch,
+        rules,
+        expr,
+        history,
+        previous,
+    };
+
+    let mut p = data
+        .packages
+        .iter()
+        .filter(|(x, pkg)| dropped(x, pkg, &filters).is_none())
+        .map(|(x, _)| x.to_string())
+        .collect::<Vec<_>>();
+    p.sort();
+
+    let mut aliases = BTreeMap::new();
+    if args.dedupe || args.dedupe_versions {
+        (p, aliases) = dedupe(&p, &data.packages, args.dedupe_versions, args.canonical);
+        if let Some(path) = &args.aliases {
+            let json = serde_json::to_string_pretty(&aliases).expect("Failed to serialize aliases");
+            fs::write(path, json).expect("Failed to write to file");
+        }
     }
 
-    // Filter by architecture
-    pkgdata = pkgdata
-        .into_iter()
-        .filter(|(_, p)| p.system == arch)
-        .collect::<HashMap<_, _>>();
-
-    // Remove broken packages
-    pkgdata = pkgdata
-        .into_iter()
-        .filter(|(_, p)| p.meta.broken != Some(true))
-        .collect::<HashMap<_, _>>();
-
-    // Remove insecure packages
-    pkgdata = pkgdata
-        .into_iter()
-        .filter(|(_, p)| p.meta.insecure != Some(true))
-        .collect::<HashMap<_, _>>();
-
-    // Remove unsupported packages
-    pkgdata = pkgdata
-        .into_iter()
-        .filter(|(_, p)| p.meta.unsupported != Some(true))
-        .collect::<HashMap<_, _>>();
+    if let (Some(nixpkgs), true) = (&args.nixpkgs, args.likely_metadata) {
+        let attrs = args.explain.as_ref().map(std::slice::from_ref).unwrap_or(&p);
+        instantiate(nixpkgs, attrs, &data.packages, &filters);
+    }
 
-    // Remove large package sets
-    if !args.everything {
-        let filteredstart = vec![
-            "linuxKernel",
-
-            "androidStudioPackages",
-            "apacheHttpdPackages",
-            "arcanPackages",
-            "beetsPackages",
-            "chickenPackages",
-            "coqPackages",
-            "cudaPackages",
-            "dhallPackages",
-            "dotnetCorePackages",
-            "dotnetPackages",
-            "dwarf-fortress-packages",
-            "elmPackages",
-            "emacs28Packages",
-            "emscriptenPackages",
-            "fdbPackages",
-            "gnuradio3_8Packages",
-            "haskellPackages",
-            "haxePackages",
-            "idrisPackages",
-            "javaPackages",
-            "kodiPackages",
-            "lispPackages",
-            "llvmPackages",
-            "lua51Packages",
-            "lua52Packages",
-            "lua53Packages",
-            "luajitPackages",
-            "nimPackages",
-            "nodePackages",
-            "ocamlPackages",
-            "octavePackages",
-            "openraPackages",
-            "perl532Packages",
-            "perl534Packages",
-            "php80Packages",
-            "php81Packages",
-            "postgresql11Packages",
-            "postgresql12Packages",
-            "postgresql13Packages",
-            "postgresql14Packages",
-            "python310Packages",
-            "python39Packages",
-            "quicklispPackagesClisp",
-            "rPackages",
-            "rubyPackages",
-            "ue4demos",
-            "wine64Packages",
-            "winePackages",
-            "wineWowPackages",
-
-            "elasticsearchPlugins",
-            "fishPlugins",
-            "graylogPlugins",
-            "gsignondPlugins",
-            "kakounePlugins",
-            "tmuxPlugins",
-            "vdrPlugins",
-            "vimPlugins",
-
-            "gnomeExtensions",
-            "passExtensions",
-            "php80Extensions",
-            "php81Extensions",
-            "vscode-extensions",
-
-            "adoptopenjdk",
-            "alephone",
-            "aspellDicts",
-            "bitcoind",
-            "CuboCore",
-            "dictdDBs",
-            "ethminer",
-            "gawkextlib",
-            "haskell.",
-            "home-assistant-component-tests",
-            "hunspellDicts",
-            "libretro.",
-            "lohit-fonts",
-            "minecraftServers",
-            "mpvScripts",
-            "openjdk",
-            "optifinePackages",
-            "pythonDocs",
-            "terraform-providers",
-            "texlive",
-            "tree-sitter-grammars",
-            "weechatScripts",
-            "zncModules",
-
-        ];
-
-        let filteredend = vec![
-            "WithCuda",
-            "withCuda",
-            "CudaMpi",
-        ];
-
-        let filteredpkgs = vec![
-            "cntk",
-            "gpu-burn",
-            "mathematica-cuda"
-        ];
-
-        pkgdata = pkgdata
-            .into_iter()
-            .filter(|(x, _)| !filteredstart.iter().any(|y| x.starts_with(y)))
-            .filter(|(x, _)| !filteredend.iter().any(|y| x.ends_with(y)))
-            .filter(|(x, _)| !filteredpkgs.iter().any(|y| x == y))
-            .collect::<HashMap<_, _>>();
+    if let Some(attr) = &args.explain {
+        match data.packages.get(attr) {
+            Some(p) => match dropped(attr, p, &filters) {
+                Some(why) => println!("{attr}: {} {why}", "dropped:".if_supports_color(Stdout, |x| x.red())),
+                None => match aliases.iter().find(|(_, a)| a.contains(attr)) {
+                    Some((c, _)) => println!(
+                        "{attr}: {} duplicate of {c}",
+                        "dropped:".if_supports_color(Stdout, |x| x.red())
+                    ),
+                    None => println!("{attr}: {}", "included".if_supports_color(Stdout, |x| x.green())),
+                },
+            },
+            None => println!("{attr}: {}", "not in packages.json".if_supports_color(Stdout, |x| x.yellow())),
+        }
+        if let (Some(p), true) = (data.packages.get(attr), args.likely_metadata) {
+            let pr = predict(attr, p, filters.history.as_ref());
+            println!("{attr}: metadata score {}: {}", pr.score, pr.reasons.join(", "));
+        }
+        return;
     }
 
-    let mut p = pkgdata.into_iter().map(|(x, _)| x).collect::<Vec<_>>();
-    p.sort();
-    if let Some(outpath) = args.output {
+    if args.likely_metadata {
+        let mut scored = p
+            .into_iter()
+            .map(|x| (predict(&x, &data.packages[&x], filters.history.as_ref()).score, x))
+            .filter(|(score, _)| *score > 0)
+            .collect::<Vec<_>>();
+        scored.sort_by(|(a, x), (b, y)| b.cmp(a).then(x.cmp(y)));
+        p = scored.into_iter().map(|(_, x)| x).collect();
+    }
+    if let (Some(outpath), Some(n)) = (&args.output, args.shards) {
+        for (i, (list, cost)) in shard(&p, n, filters.history.as_ref()).into_iter().enumerate() {
+            let path = format!("{outpath}.{i}");
+            fs::write(&path, list.join("\n")).expect("Failed to write to file");
+            println!("{path}: {} packages, estimated {:.0}s", list.len(), cost);
+        }
+    } else if let Some(outpath) = &args.output {
         fs::write(outpath, p.join("\n")).expect("Failed to write to file");
     } else {
         for pkg in p {
             println!("{}", pkg);
         }
     }
-    
+}
+
+/// Writes the derivations of `attrs` to the store, so `predict` can read their build inputs
+fn instantiate(nixpkgs: &str, attrs: &[String], packages: &HashMap<String, Package>, f: &Filters) {
+    let missing = attrs
+        .iter()
+        .filter(|a| {
+            packages
+                .get(*a)
+                .and_then(|p| p.drvpath.as_ref())
+                .is_some_and(|d| !Path::new(d).exists())
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    for (attr, e) in eval::instantiate(nixpkgs, &f.arch, &f.args.overlay, &missing) {
+        eprintln!("{} {attr}: {e}", "warning:".if_supports_color(Stdout, |x| x.yellow()));
+    }
+}
+
+/// Why `attr` is left out of the list, or `None` if it is included
+fn dropped(attr: &str, p: &Package, f: &Filters) -> Option<String> {
+    let (args, arch) = (f.args, f.arch.as_str());
+    // Filter by license
+    if args.free && p.meta.unfree == Some(true) {
+        return Some("unfree license".to_string());
+    } else if args.unfree && p.meta.unfree != Some(true) {
+        return Some("free license".to_string());
+    }
+
+    // Filter by architecture
+    if p.system != arch {
+        return Some(format!("system {} is not {arch}", p.system));
+    }
+
+    // Remove broken, insecure and unsupported packages
+    if p.meta.broken == Some(true) {
+        return Some("broken".to_string());
+    }
+    if p.meta.insecure == Some(true) {
+        return Some("insecure".to_string());
+    }
+    if p.meta.unsupported == Some(true) {
+        return Some("unsupported".to_string());
+    }
+
+    // Filter by meta expression
+    if let Some(e) = &f.expr {
+        if !e.eval(&p.meta) {
+            return Some(format!("does not match filter {e}"));
+        }
+    }
+
+    // Only keep what produced metadata last time and what is new since
+    if args.incremental {
+        let produced = f.history.as_ref().and_then(|h| h.produced(attr)) == Some(true);
+        let new = f.previous.as_ref().map(|p| !p.contains(attr)) == Some(true);
+        if !produced && !new {
+            return Some("did not produce metadata in the previous run and is not new".to_string());
+        }
+    }
+
+    // Remove large package sets
+    let rule = f.rules.as_ref()?.find(attr)?;
+    Some(match &rule.reason {
+        Some(r) => format!("excluded by {rule}: {r}"),
+        None => format!("excluded by {rule}"),
+    })
 }
@@ -0,0 +1,46 @@
+use crate::{history::History, Package};
+use std::fs;
+
+/// Build inputs that usually mean a package installs a desktop file or AppStream metadata, with the
+/// name they appear as in derivation paths and their weight
+const HOOKS: [(&str, &str, i32); 4] = [
+    ("appstream-glib", "-appstream-glib-", 50),
+    ("desktop-file-utils", "-desktop-file-utils-", 40),
+    ("copyDesktopItems", "-copy-desktop-items", 40),
+    ("wrapGAppsHook", "-wrap-gapps-hook", 30),
+];
+
+#[derive(Debug, Default)]
+pub struct Prediction {
+    pub score: i32,
+    pub reasons: Vec<String>,
+}
+
+impl Prediction {
+    fn add(&mut self, points: i32, reason: &str) {
+        self.score += points;
+        self.reasons.push(format!("{reason} ({points:+})"));
+    }
+}
+
+/// Scores how likely `attr` ships AppStream metadata, from its previous result, its build inputs
+/// (only known for local evaluations, which record derivation paths) and `meta.mainProgram`
+pub fn predict(attr: &str, p: &Package, history: Option<&History>) -> Prediction {
+    let mut pr = Prediction::default();
+    match history.and_then(|h| h.produced(attr)) {
+        Some(true) => pr.add(100, "produced metadata last time"),
+        Some(false) => pr.add(-100, "had no metadata last time"),
+        None => (),
+    }
+    if let Some(drv) = p.drvpath.as_ref().and_then(|d| fs::read_to_string(d).ok()) {
+        for (name, needle, points) in HOOKS {
+            if drv.contains(needle) {
+                pr.add(points, &format!("depends on {name}"));
+            }
+        }
+    }
+    if p.meta.mainprogram.is_some() {
+        pr.add(10, "has meta.mainProgram");
+    }
+    pr
+}
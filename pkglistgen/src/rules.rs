@@ -0,0 +1,131 @@
+use regex::Regex;
+use std::{fmt, fs};
+
+/// Rules used when no rules file is given
+pub const DEFAULT: &str = include_str!("../exclude.conf");
+
+#[derive(Debug)]
+enum Matcher {
+    Prefix(String),
+    Suffix(String),
+    Exact(String),
+    /// Globs are translated to anchored regexes
+    Glob(Regex),
+    Regex(Regex),
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    matcher: Matcher,
+    kind: String,
+    pattern: String,
+    file: String,
+    line: usize,
+    /// Comment directly above the group of rules
+    pub reason: Option<String>,
+}
+
+impl Rule {
+    fn matches(&self, attr: &str) -> bool {
+        match &self.matcher {
+            Matcher::Prefix(p) => attr.starts_with(p),
+            Matcher::Suffix(s) => attr.ends_with(s),
+            Matcher::Exact(e) => attr == e,
+            Matcher::Glob(r) | Matcher::Regex(r) => r.is_match(attr),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({}:{})", self.kind, self.pattern, self.file, self.line)
+    }
+}
+
+pub fn globregex(glob: &str) -> String {
+    let mut r = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => r.push_str(".*"),
+            '?' => r.push('.'),
+            c => r.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    r.push('$');
+    r
+}
+
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Parses a rules file. Lines starting with `#` are comments, and the comment lines directly
+    /// above a group of rules are kept as the reason for them until the next blank line.
+    pub fn parse(file: &str, contents: &str) -> Result<Self, String> {
+        let mut rules = vec![];
+        let mut comment: Vec<&str> = vec![];
+        let mut ingroup = false;
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                comment.clear();
+                ingroup = false;
+                continue;
+            }
+            if let Some(c) = line.strip_prefix('#') {
+                if ingroup {
+                    comment.clear();
+                    ingroup = false;
+                }
+                comment.push(c.trim());
+                continue;
+            }
+            ingroup = true;
+
+            let (kind, pattern) = line
+                .split_once(char::is_whitespace)
+                .map(|(k, p)| (k, p.trim()))
+                .ok_or_else(|| format!("{file}:{}: expected `KIND PATTERN`", i + 1))?;
+            let regex = |r: &str| {
+                Regex::new(&format!("^(?:{r})$")).map_err(|e| format!("{file}:{}: {e}", i + 1))
+            };
+            let matcher = match kind {
+                "prefix" => Matcher::Prefix(pattern.to_string()),
+                "suffix" => Matcher::Suffix(pattern.to_string()),
+                "exact" => Matcher::Exact(pattern.to_string()),
+                "glob" => Matcher::Glob(
+                    Regex::new(&globregex(pattern)).map_err(|e| format!("{file}:{}: {e}", i + 1))?,
+                ),
+                "regex" => Matcher::Regex(regex(pattern)?),
+                k => return Err(format!("{file}:{}: unknown rule kind `{k}`", i + 1)),
+            };
+            let reason = comment
+                .iter()
+                .filter(|c| !c.is_empty())
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ");
+            rules.push(Rule {
+                matcher,
+                kind: kind.to_string(),
+                pattern: pattern.to_string(),
+                file: file.to_string(),
+                line: i + 1,
+                reason: Some(reason).filter(|r| !r.is_empty()),
+            });
+        }
+        Ok(Rules { rules })
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        Self::parse(path, &contents)
+    }
+
+    /// The first rule excluding `attr`
+    pub fn find(&self, attr: &str) -> Option<&Rule> {
+        self.rules.iter().find(|r| r.matches(attr))
+    }
+}
@@ -0,0 +1,42 @@
+use crate::history::History;
+
+/// Splits `attrs` into `n` lists with about the same total cost, each keeping the order of `attrs` so
+/// packages ranked first by `--likely-metadata` are still processed first. The cost of a package is
+/// its processing time in the `history` run, and the average of the known times for packages without
+/// one, so all packages cost the same without history.
+pub fn shard(attrs: &[String], n: usize, history: Option<&History>) -> Vec<(Vec<String>, f64)> {
+    let known = attrs
+        .iter()
+        .filter_map(|a| history.and_then(|h| h.seconds(a)))
+        .collect::<Vec<_>>();
+    let default = if known.is_empty() {
+        1.0
+    } else {
+        known.iter().sum::<f64>() / known.len() as f64
+    };
+
+    let mut costs = attrs
+        .iter()
+        .enumerate()
+        .map(|(i, a)| (history.and_then(|h| h.seconds(a)).unwrap_or(default), i))
+        .collect::<Vec<_>>();
+    // Placing the most expensive packages first keeps the shards close to each other
+    costs.sort_by(|(x, i), (y, j)| y.total_cmp(x).then(i.cmp(j)));
+
+    let mut shards: Vec<(Vec<usize>, f64)> = vec![(vec![], 0.0); n];
+    for (cost, i) in costs {
+        let s = shards
+            .iter_mut()
+            .min_by(|(_, x), (_, y)| x.total_cmp(y))
+            .expect("at least one shard");
+        s.0.push(i);
+        s.1 += cost;
+    }
+    shards
+        .into_iter()
+        .map(|(mut s, cost)| {
+            s.sort();
+            (s.into_iter().map(|i| attrs[i].clone()).collect(), cost)
+        })
+        .collect()
+}
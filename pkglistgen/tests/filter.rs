@@ -0,0 +1,71 @@
+use std::{fs, process::Command};
+
+const DIR: &str = "tests/data/filter";
+
+fn run(filter: &str) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_pkglistgen"))
+        .arg("--everything")
+        .arg("-p")
+        .arg(format!("{DIR}/packages.json"))
+        .arg("--filter")
+        .arg(filter)
+        .output()
+        .unwrap()
+}
+
+/// Lists `packages.json` with the expression in every `{name}.filter` and compares the attributes
+/// to `{name}.out`
+#[test]
+fn golden() {
+    let mut cases = fs::read_dir(DIR)
+        .unwrap()
+        .flatten()
+        .filter_map(|e| {
+            e.file_name()
+                .to_str()
+                .and_then(|n| n.strip_suffix(".filter"))
+                .map(|n| n.to_string())
+        })
+        .collect::<Vec<_>>();
+    cases.sort();
+    assert!(!cases.is_empty());
+
+    for case in cases {
+        let filter = fs::read_to_string(format!("{DIR}/{case}.filter")).unwrap();
+        let out = run(filter.trim());
+        assert!(
+            out.status.success(),
+            "{case}: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let actual = String::from_utf8(out.stdout).unwrap();
+        let expected = fs::read_to_string(format!("{DIR}/{case}.out")).unwrap();
+        assert!(
+            actual.lines().eq(expected.lines()),
+            "{case}: `{}` got\n{actual}\nexpected\n{expected}",
+            filter.trim()
+        );
+    }
+}
+
+#[test]
+fn invalid() {
+    for filter in [
+        "license:GPL* and",
+        "(free or unfree",
+        "free)",
+        "not",
+        "and free",
+        "license",
+        "free:yes",
+        "homepage:*",
+    ] {
+        let out = run(filter);
+        assert!(!out.status.success(), "`{filter}` was accepted");
+        assert!(
+            String::from_utf8_lossy(&out.stderr).contains("filter"),
+            "`{filter}`: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+}
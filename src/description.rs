@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use xmltree::{Element, XMLNode};
+
+pub const LANG: &str = "xml:lang";
+
+/// xmltree drops the namespace prefix of attributes, so `xml:lang` is read as `lang` and would be
+/// written back without its prefix. Restores the prefix on `e` and all its descendants.
+pub fn fixlangattrs(e: &mut Element) {
+    if let Some(l) = e.attributes.remove("lang") {
+        e.attributes.insert(LANG.to_string(), l);
+    }
+    for c in e.children.iter_mut() {
+        if let XMLNode::Element(c) = c {
+            fixlangattrs(c);
+        }
+    }
+}
+
+fn lang(e: &Element, inherited: &str) -> String {
+    e.attributes
+        .get(LANG)
+        .cloned()
+        .unwrap_or_else(|| inherited.to_string())
+}
+
+/// Copy of `e` without its language
+fn unlocalized(e: &Element) -> Element {
+    let mut e = e.clone();
+    e.attributes.remove(LANG);
+    e
+}
+
+/// The description for `lang`, created from the attributes of `template` on first use
+fn bucket<'a>(
+    map: &'a mut BTreeMap<String, Element>,
+    lang: &str,
+    template: &Element,
+) -> &'a mut Element {
+    map.entry(lang.to_string()).or_insert_with(|| {
+        let mut d = unlocalized(template);
+        d.children.clear();
+        if !lang.is_empty() {
+            d.attributes.insert(LANG.to_string(), lang.to_string());
+        }
+        d
+    })
+}
+
+/// Sorts the children of a description into per-language descriptions. Children inherit the
+/// language of their parent, and lists are split into one list per language of their items.
+fn split(d: &Element, map: &mut BTreeMap<String, Element>) {
+    let dlang = lang(d, "");
+    for c in d.children.iter().filter_map(|c| c.as_element()) {
+        let clang = lang(c, &dlang);
+        if c.name == "ul" || c.name == "ol" {
+            let mut lists: Vec<(String, Element)> = vec![];
+            for li in c.children.iter().filter_map(|c| c.as_element()) {
+                let llang = lang(li, &clang);
+                let i = match lists.iter().position(|(l, _)| *l == llang) {
+                    Some(i) => i,
+                    None => {
+                        let mut list = unlocalized(c);
+                        list.children.clear();
+                        lists.push((llang, list));
+                        lists.len() - 1
+                    }
+                };
+                lists[i].1.children.push(XMLNode::Element(unlocalized(li)));
+            }
+            for (l, list) in lists {
+                bucket(map, &l, d).children.push(XMLNode::Element(list));
+            }
+        } else {
+            bucket(map, &clang, d)
+                .children
+                .push(XMLNode::Element(unlocalized(c)));
+        }
+    }
+}
+
+/// Rewrites the descriptions of `x` and of its releases so there is one `<description>` per
+/// language, as required by AppStream, instead of paragraphs and list items carrying their own
+/// `xml:lang`. Content of each language keeps its document order, untranslated content is not
+/// copied into translations, and attributes other than the language are kept. The descriptions
+/// replace the first original one, untranslated first and then sorted by language.
+pub fn normalize(x: &mut Element) {
+    if let Some(pos) = x
+        .children
+        .iter()
+        .position(|c| c.as_element().map(|e| e.name == "description") == Some(true))
+    {
+        let mut map = BTreeMap::new();
+        for c in std::mem::take(&mut x.children) {
+            match c {
+                XMLNode::Element(d) if d.name == "description" => split(&d, &mut map),
+                c => x.children.push(c),
+            }
+        }
+        for (i, d) in map.into_values().enumerate() {
+            x.children.insert(pos + i, XMLNode::Element(d));
+        }
+    }
+
+    if let Some(releases) = x.get_mut_child("releases") {
+        for r in releases
+            .children
+            .iter_mut()
+            .filter_map(|c| c.as_mut_element())
+            .filter(|r| r.name == "release")
+        {
+            normalize(r);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::fs;
+
+/// A key of a group, with the locale of a localized key such as `Name[de]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub locale: Option<String>,
+    /// Raw value, still escaped
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Group {
+    pub name: String,
+    pub entries: Vec<Entry>,
+}
+
+/// A parsed file in the Desktop Entry format, such as a desktop file or a D-Bus service file
+#[derive(Debug, Clone, Default)]
+pub struct DesktopEntry {
+    /// Path of the file
+    pub file: String,
+    pub groups: Vec<Group>,
+}
+
+/// Resolves the `\s`, `\n`, `\t`, `\r`, `\\` and `\;` escape sequences
+pub fn unescape(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+    let mut chars = v.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('\\') => out.push('\\'),
+            Some(';') => out.push(';'),
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Splits a list value on unescaped `;` and unescapes the items, dropping empty ones
+pub fn splitlist(v: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut cur = String::new();
+    let mut chars = v.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                cur.push(c);
+                if let Some(n) = chars.next() {
+                    cur.push(n);
+                }
+            }
+            ';' => items.push(std::mem::take(&mut cur)),
+            _ => cur.push(c),
+        }
+    }
+    items.push(cur);
+    items
+        .iter()
+        .map(|i| unescape(i.trim()))
+        .filter(|i| !i.is_empty())
+        .collect()
+}
+
+/// Locale names to try for `locale`, from most to least specific, as described by the
+/// specification: `lang_COUNTRY@MODIFIER`, `lang_COUNTRY`, `lang@MODIFIER`, `lang`
+fn localecandidates(locale: &str) -> Vec<String> {
+    let (rest, modifier) = match locale.split_once('@') {
+        Some((r, m)) => (r, Some(m)),
+        None => (locale, None),
+    };
+    let rest = rest.split('.').next().unwrap_or_default();
+    let (lang, country) = match rest.split_once('_') {
+        Some((l, c)) => (l, Some(c)),
+        None => (rest, None),
+    };
+    let mut c = vec![];
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        c.push(format!("{lang}_{country}@{modifier}"));
+    }
+    if let Some(country) = country {
+        c.push(format!("{lang}_{country}"));
+    }
+    if let Some(modifier) = modifier {
+        c.push(format!("{lang}@{modifier}"));
+    }
+    c.push(lang.to_string());
+    c
+}
+
+impl Group {
+    fn raw(&self, key: &str, locale: Option<&str>) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|e| e.key == key && e.locale.as_deref() == locale)
+            .map(|e| e.value.as_str())
+    }
+
+    /// Unescaped value of an unlocalized key
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.raw(key, None).map(unescape)
+    }
+
+    /// Value of `key` in `locale`, falling back to less specific locales and the unlocalized value
+    pub fn localized(&self, key: &str, locale: &str) -> Option<String> {
+        localecandidates(locale)
+            .iter()
+            .find_map(|l| self.raw(key, Some(l)))
+            .or_else(|| self.raw(key, None))
+            .map(unescape)
+    }
+
+    /// All translations of `key` as `(locale, value)`
+    pub fn translations(&self, key: &str) -> Vec<(String, String)> {
+        self.entries
+            .iter()
+            .filter(|e| e.key == key)
+            .filter_map(|e| e.locale.clone().map(|l| (l, unescape(&e.value))))
+            .collect()
+    }
+
+    pub fn list(&self, key: &str) -> Vec<String> {
+        self.raw(key, None).map(splitlist).unwrap_or_default()
+    }
+
+    pub fn bool(&self, key: &str) -> bool {
+        self.raw(key, None).map(|v| v.trim() == "true") == Some(true)
+    }
+}
+
+impl DesktopEntry {
+    /// Parses the contents of a file. Comments, blank lines and malformed lines are skipped, and only
+    /// the first occurrence of a key within a group is kept.
+    pub fn parse(file: &str, contents: &str) -> Self {
+        let mut groups: Vec<Group> = vec![];
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                groups.push(Group {
+                    name: name.to_string(),
+                    entries: vec![],
+                });
+                continue;
+            }
+            let (group, (k, v)) = match (groups.last_mut(), line.split_once('=')) {
+                (Some(g), Some(kv)) => (g, kv),
+                _ => continue,
+            };
+            let k = k.trim();
+            let (key, locale) = match k.split_once('[') {
+                Some((key, l)) => match l.strip_suffix(']') {
+                    Some(l) => (key, Some(l.to_string())),
+                    None => continue,
+                },
+                None => (k, None),
+            };
+            if group.raw(key, locale.as_deref()).is_some() {
+                continue;
+            }
+            group.entries.push(Entry {
+                key: key.to_string(),
+                locale,
+                value: v.trim_start().to_string(),
+            });
+        }
+        DesktopEntry {
+            file: file.to_string(),
+            groups,
+        }
+    }
+
+    pub fn read(file: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(file).map_err(|e| format!("{file}: {e}"))?;
+        Ok(Self::parse(file, &contents))
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|g| g.name == name)
+    }
+
+    /// The `[Desktop Entry]` group. Keys of action groups are never returned from here.
+    pub fn main(&self) -> Option<&Group> {
+        self.group("Desktop Entry")
+    }
+
+    /// Unescaped value of a key in the main group
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.main().and_then(|g| g.get(key))
+    }
+
+    /// File name, which is also the desktop id
+    pub fn filename(&self) -> &str {
+        self.file.split('/').next_back().unwrap_or_default()
+    }
+
+    /// Why the entry is not shown in menus at all, if it is not. Entries limited to some desktops
+    /// with `OnlyShowIn` are real applications and not hidden.
+    pub fn hidden(&self) -> Option<String> {
+        let main = match self.main() {
+            Some(m) => m,
+            None => return Some("no [Desktop Entry] group".to_string()),
+        };
+        if main.bool("Hidden") {
+            return Some("Hidden=true".to_string());
+        }
+        if main.bool("NoDisplay") {
+            return Some("NoDisplay=true".to_string());
+        }
+        match main.get("Type").as_deref() {
+            None | Some("Application") => None,
+            Some(t) => Some(format!("Type={t}")),
+        }
+    }
+}
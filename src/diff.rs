@@ -0,0 +1,299 @@
+use crate::description::{fixlangattrs, LANG};
+use owo_colors::{OwoColorize, Stream::Stdout};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+use xmltree::{Element, XMLNode};
+
+/// A field whose values differ between two versions of a component
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    /// Element name, with the language for translated fields such as `summary[de]`
+    pub field: String,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+/// A component that changed its id, matched by package and launchable or by being the only
+/// component of its package that was added and removed
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Renamed {
+    pub from: String,
+    pub to: String,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CatalogDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub renamed: Vec<Renamed>,
+    pub changed: BTreeMap<String, Vec<FieldChange>>,
+}
+
+fn text(e: &Element) -> String {
+    let mut parts = vec![];
+    fn collect(e: &Element, parts: &mut Vec<String>) {
+        for c in &e.children {
+            match c {
+                XMLNode::Text(t) | XMLNode::CData(t) => parts.push(t.to_string()),
+                XMLNode::Element(c) => collect(c, parts),
+                _ => (),
+            }
+        }
+    }
+    collect(e, &mut parts);
+    parts.join(" ").split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Readable value of a field: its text with the attributes other than the language
+fn value(e: &Element) -> String {
+    let mut attrs = e
+        .attributes
+        .iter()
+        .filter(|(k, _)| k.as_str() != LANG)
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>();
+    attrs.sort();
+    let t = text(e);
+    match (attrs.is_empty(), t.is_empty()) {
+        (true, _) => t,
+        (false, true) => format!("[{}]", attrs.join(" ")),
+        (false, false) => format!("[{}] {t}", attrs.join(" ")),
+    }
+}
+
+/// Values of every top level field of a component
+fn fields(x: &Element) -> BTreeMap<String, BTreeSet<String>> {
+    let mut f: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for c in x.children.iter().filter_map(|c| c.as_element()) {
+        let key = match c.attributes.get(LANG) {
+            Some(l) => format!("{}[{l}]", c.name),
+            None => c.name.clone(),
+        };
+        f.entry(key).or_default().insert(value(c));
+    }
+    if let Some(t) = x.attributes.get("type") {
+        f.entry("type".to_string()).or_default().insert(t.clone());
+    }
+    f
+}
+
+/// Field level differences between two versions of a component
+pub fn compare(old: &Element, new: &Element) -> Vec<FieldChange> {
+    let (old, new) = (fields(old), fields(new));
+    let empty = BTreeSet::new();
+    old.keys()
+        .chain(new.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|k| {
+            let (o, n) = (old.get(k).unwrap_or(&empty), new.get(k).unwrap_or(&empty));
+            if o == n {
+                return None;
+            }
+            Some(FieldChange {
+                field: k.clone(),
+                removed: o.difference(n).cloned().collect(),
+                added: n.difference(o).cloned().collect(),
+            })
+        })
+        .collect()
+}
+
+fn child(x: &Element, name: &str) -> Option<String> {
+    x.get_child(name)
+        .map(text)
+        .filter(|t| !t.is_empty())
+}
+
+fn parse(file: &str) -> Result<Element, String> {
+    let f = fs::read_to_string(file).map_err(|e| format!("{file}: {e}"))?;
+    let mut x = Element::parse(f.as_bytes()).map_err(|e| format!("{file}: {e:?}"))?;
+    fixlangattrs(&mut x);
+    Ok(x)
+}
+
+/// Components of an output directory of this tool or of a merged catalog, keyed by id. Components
+/// with an id that is already taken are keyed as `{id} ({pkgname})`.
+pub fn load(path: &str) -> Result<BTreeMap<String, Element>, String> {
+    let mut components = vec![];
+    let p = Path::new(path);
+    if p.is_dir() {
+        let dir = if p.join("metadata").is_dir() {
+            p.join("metadata")
+        } else {
+            p.to_path_buf()
+        };
+        let mut files = fs::read_dir(&dir)
+            .map_err(|e| format!("{}: {e}", dir.display()))?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|f| f.extension().and_then(|e| e.to_str()) == Some("xml"))
+            .collect::<Vec<_>>();
+        files.sort();
+        for f in files {
+            let x = parse(&f.to_string_lossy())?;
+            let stem = f
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let fileid = stem.split("::").last().unwrap_or_default().to_string();
+            components.push((fileid, x));
+        }
+    } else {
+        let x = parse(path)?;
+        if x.name == "components" {
+            for c in x.children.into_iter() {
+                if let XMLNode::Element(c) = c {
+                    if c.name == "component" {
+                        components.push((String::new(), c));
+                    }
+                }
+            }
+        } else {
+            components.push((String::new(), x));
+        }
+    }
+
+    let mut map = BTreeMap::new();
+    for (fileid, x) in components {
+        let id = child(&x, "id").unwrap_or(fileid);
+        let key = if map.contains_key(&id) {
+            format!("{id} ({})", child(&x, "pkgname").unwrap_or_default())
+        } else {
+            id
+        };
+        map.insert(key, x);
+    }
+    Ok(map)
+}
+
+fn launchables(x: &Element) -> BTreeSet<String> {
+    x.children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .filter(|c| c.name == "launchable")
+        .map(text)
+        .collect()
+}
+
+/// Compares two catalogs keyed by component id
+pub fn diff(old: &BTreeMap<String, Element>, new: &BTreeMap<String, Element>) -> CatalogDiff {
+    let mut d = CatalogDiff::default();
+    let mut removed = old
+        .keys()
+        .filter(|k| !new.contains_key(*k))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut added = new
+        .keys()
+        .filter(|k| !old.contains_key(*k))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // Match id changes within a package
+    for r in removed.clone() {
+        let pkg = child(&old[&r], "pkgname");
+        let samepkg = |k: &&String, m: &BTreeMap<String, Element>| child(&m[*k], "pkgname") == pkg;
+        let candidates = added
+            .iter()
+            .filter(|a| pkg.is_some() && samepkg(a, new))
+            .collect::<Vec<_>>();
+        let soleremoved = removed.iter().filter(|o| samepkg(o, old)).count() == 1;
+        let launch = launchables(&old[&r]);
+        let to = candidates
+            .iter()
+            .find(|a| !launch.is_empty() && launchables(&new[**a]) == launch)
+            .or_else(|| candidates.first().filter(|_| candidates.len() == 1 && soleremoved))
+            .map(|a| a.to_string());
+        if let Some(to) = to {
+            d.renamed.push(Renamed {
+                changes: compare(&old[&r], &new[&to])
+                    .into_iter()
+                    .filter(|c| c.field != "id")
+                    .collect(),
+                from: r.clone(),
+                to: to.clone(),
+            });
+            removed.retain(|x| *x != r);
+            added.retain(|x| *x != to);
+        }
+    }
+    d.added = added;
+    d.removed = removed;
+
+    for (k, o) in old {
+        if let Some(n) = new.get(k) {
+            let changes = compare(o, n);
+            if !changes.is_empty() {
+                d.changed.insert(k.clone(), changes);
+            }
+        }
+    }
+    d
+}
+
+impl CatalogDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.renamed.is_empty()
+            && self.changed.is_empty()
+    }
+
+    fn printchanges(changes: &[FieldChange]) {
+        for c in changes {
+            for r in &c.removed {
+                println!(
+                    "    {}",
+                    format!("- {}: {r}", c.field).if_supports_color(Stdout, |x| x.red())
+                );
+            }
+            for a in &c.added {
+                println!(
+                    "    {}",
+                    format!("+ {}: {a}", c.field).if_supports_color(Stdout, |x| x.green())
+                );
+            }
+        }
+    }
+
+    pub fn print(&self) {
+        for a in &self.added {
+            println!("{} {a}", "added:".if_supports_color(Stdout, |x| x.green()));
+        }
+        for r in &self.removed {
+            println!("{} {r}", "removed:".if_supports_color(Stdout, |x| x.red()));
+        }
+        for r in &self.renamed {
+            println!(
+                "{} {} -> {}",
+                "renamed:".if_supports_color(Stdout, |x| x.yellow()),
+                r.from,
+                r.to
+            );
+            Self::printchanges(&r.changes);
+        }
+        for (id, changes) in &self.changed {
+            println!("{} {id}", "changed:".if_supports_color(Stdout, |x| x.cyan()));
+            Self::printchanges(changes);
+        }
+        println!(
+            "{} {} added, {} removed, {} renamed, {} changed",
+            "Summary:".if_supports_color(Stdout, |x| x.bold()),
+            self.added.len(),
+            self.removed.len(),
+            self.renamed.len(),
+            self.changed.len()
+        );
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, data).map_err(|e| format!("{path}: {e}"))
+    }
+}
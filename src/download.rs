@@ -0,0 +1,56 @@
+use curl::easy::Easy;
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path};
+
+/// Directory screenshots are cached in between runs
+pub const CACHEDIR: &str = "cache/downloads";
+
+fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    let mut dst = Vec::new();
+    let mut easy = Easy::new();
+    easy.url(url).map_err(|e| e.to_string())?;
+    easy.follow_location(true).map_err(|e| e.to_string())?;
+    easy.fail_on_error(true).map_err(|e| e.to_string())?;
+
+    {
+        let mut transfer = easy.transfer();
+        transfer
+            .write_function(|data| {
+                dst.extend_from_slice(data);
+                Ok(data.len())
+            })
+            .map_err(|e| e.to_string())?;
+        transfer.perform().map_err(|e| format!("{}: {}", url, e))?;
+    }
+    Ok(dst)
+}
+
+/// Fetches `url` into the download cache `cachedir` and returns the path of the cached file.
+/// Files already in the cache are reused without touching the network, so this is only meant for
+/// content that does not change under the same URL, such as screenshots.
+pub fn dlcached(url: &str, cachedir: &str) -> Result<String, String> {
+    let hash = Sha256::digest(url.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    let cachepath = format!("{cachedir}/{hash}");
+    if Path::new(&cachepath).is_file() {
+        return Ok(cachepath);
+    }
+
+    fs::create_dir_all(cachedir).map_err(|e| e.to_string())?;
+    let dst = fetch(url)?;
+
+    // Write to a temporary name first so an interrupted run never leaves a truncated cache entry
+    let partial = format!("{cachepath}.part");
+    fs::write(&partial, &dst).map_err(|e| e.to_string())?;
+    fs::rename(&partial, &cachepath).map_err(|e| e.to_string())?;
+    Ok(cachepath)
+}
+
+/// Downloads `url` to `path` on every call, for files that may change upstream such as the custom
+/// metainfo and icons of custom.json
+pub fn dl(url: &str, path: &str) -> Result<(), String> {
+    let dst = fetch(url)?;
+    fs::write(path, dst).map_err(|e| format!("{path}: {e}"))
+}
@@ -1,15 +1,24 @@
-use curl::easy::Easy;
-use image;
+use crate::{
+    description::{fixlangattrs, normalize, LANG},
+    desktop::DesktopEntry,
+    download::{dl, CACHEDIR},
+    icon::{dlicons, findicons, localicons, remoteicons, stockicon, IconNames, IconPolicy, Policy},
+    languages::addlanguages,
+    legacy::modernize,
+    matcher::{resolve, unmatchedpins},
+    media::mirrorscreenshots,
+    nondesktop::fixnondesktop,
+    provides::autoprovides,
+    report::PkgReport,
+    sanitize::{fixentities, parse, sanitize, Changes},
+};
 use owo_colors::{OwoColorize, Stream::Stdout};
 use std::{
     collections::HashMap,
     fs::{self, File},
-    io::{BufRead, BufReader, Write},
-    os::unix::prelude::PermissionsExt,
     path::Path,
-    process::Command,
 };
-use xmltree::Element;
+use xmltree::{Element, ParseError, XMLNode};
 
 #[derive(Default, Debug)]
 pub struct PkgData {
@@ -17,14 +26,27 @@ pub struct PkgData {
     pub icon: Option<String>,
     pub outputicon: Option<String>,
     pub outputmetainfo: Option<String>,
+    /// Pinned desktop files, keyed by metainfo file name or component id
+    pub pairs: HashMap<String, String>,
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct GenOptions {
+    /// Base URL mirrored screenshots are served from. Screenshots are only mirrored when set
+    pub mediaurl: Option<String>,
+    /// How cached icon files are named, custom output icon names are used as they are
+    pub iconnames: IconNames,
+    /// Which icon types desktop applications get. Remote icons need `mediaurl`.
+    pub iconpolicy: IconPolicy,
+    /// How many times smaller than a cached size a PNG icon may be to be scaled up to it
+    pub maxupscale: f32,
 }
 
 fn findfiles(path: &Path, ext: &str) -> Result<Vec<String>, String> {
     let mut files: Vec<String> = Vec::new();
     match fs::read_dir(path) {
         Ok(x) => {
-            for entry in x {
-                let entry = entry.unwrap();
+            for entry in x.flatten() {
                 let p = entry.path();
                 if p.is_file() {
                     if let Some(x) = p.as_path().extension() {
@@ -34,53 +56,72 @@ fn findfiles(path: &Path, ext: &str) -> Result<Vec<String>, String> {
                     }
                 }
             }
+            files.sort();
             Ok(files)
         }
         Err(_) => Err("No file in directory".to_string()),
     }
 }
 
-fn dl(url: &str, path: &str) {
-    let mut dst = Vec::new();
-    let mut easy = Easy::new();
-    easy.url(url).unwrap();
-
-    {
-        let mut transfer = easy.transfer();
-        transfer
-            .write_function(|data| {
-                dst.extend_from_slice(data);
-                Ok(data.len())
-            })
-            .unwrap();
-        transfer.perform().unwrap();
-    }
+/// Creates an element containing only `text`. Unlike parsing a formatted string this cannot fail on
+/// names containing markup characters.
+pub(crate) fn textelement(name: &str, text: &str) -> Element {
+    let mut e = Element::new(name);
+    e.children.push(XMLNode::Text(text.to_string()));
+    e
+}
 
-    let mut file = File::create(path).expect("Failed to create file");
-    if let Err(e) = file.write_all(&dst) {
-        panic!("{}", e)
+/// Records the result of processing a single metainfo file
+fn record(pkg: &str, res: Result<String, String>, report: &mut PkgReport) {
+    match res {
+        Ok(id) => report.components.push(format!("{pkg}::{id}")),
+        Err(e) => report.error(pkg, e),
     }
 }
 
-pub fn findmeta(path: String, meta: String, pkg: &str, pkgdata: PkgData) {
+pub fn findmeta(
+    path: String,
+    meta: String,
+    pkg: &str,
+    pkgdata: PkgData,
+    opts: &GenOptions,
+    report: &mut PkgReport,
+) {
     let meta = match findfiles(Path::new(&format!("{}/share/{meta}", path)), "xml") {
         Ok(x) => x,
         Err(_) => {
-            println!("No metadata files foud");
-            std::process::exit(1);
+            report.error(pkg, "No metadata files found".to_string());
+            return;
         }
     };
-    genmeta(path, meta, pkg, pkgdata)
+    genmeta(path, meta, pkg, pkgdata, opts, report)
 }
 
-pub fn dlmeta(path: String, metaurl: String, pkg: &str, pkgdata: PkgData) {
-    let name = metaurl.split('/').last().unwrap();
-    dl(&metaurl, &format!("tmp/{}", &name));
+pub fn dlmeta(
+    path: String,
+    metaurl: String,
+    pkg: &str,
+    pkgdata: PkgData,
+    opts: &GenOptions,
+    report: &mut PkgReport,
+) {
+    let name = metaurl.split('/').next_back().unwrap_or_default();
+    if let Err(e) = dl(&metaurl, &format!("tmp/{}", &name)) {
+        report.error(pkg, e);
+        return;
+    }
     let meta = vec![format!("tmp/{}", &name)];
-    genmeta(path, meta, pkg, pkgdata)
+    genmeta(path, meta, pkg, pkgdata, opts, report)
 }
 
-fn genmeta(path: String, meta: Vec<String>, pkg: &str, pkgdata: PkgData) {
+fn genmeta(
+    path: String,
+    meta: Vec<String>,
+    pkg: &str,
+    pkgdata: PkgData,
+    opts: &GenOptions,
+    report: &mut PkgReport,
+) {
     let desktops = match findfiles(
         Path::new(&format!("{}/share/applications", path)),
         "desktop",
@@ -91,609 +132,365 @@ fn genmeta(path: String, meta: Vec<String>, pkg: &str, pkgdata: PkgData) {
                 "{pkg}: {}",
                 "No desktop files found".if_supports_color(Stdout, |x| x.yellow())
             );
+            for msg in unmatchedpins(&meta, &[], &pkgdata.pairs) {
+                report.error(pkg, msg);
+            }
+            let components = meta.iter().map(|m| (m.as_str(), None)).collect::<Vec<_>>();
+            let primary = primarycomponent(&components, pkg);
             for m in meta {
-                xmlparse_nondesktop(m, pkg);
+                let isprimary = primary.as_deref() == Some(m.as_str());
+                record(
+                    pkg,
+                    xmlparse_nondesktop(&path, m, isprimary, pkg, opts, report),
+                    report,
+                );
             }
             return;
         }
     };
 
-    if meta.len() == 1 && desktops.len() == 1 {
-        xmlparse(
+    let mut entries = vec![];
+    for d in &desktops {
+        match DesktopEntry::read(d) {
+            Ok(e) => {
+                if let Some(why) = e.hidden() {
+                    println!(
+                        "{pkg}: {}",
+                        format!("{}: hidden by {why}", e.filename())
+                            .if_supports_color(Stdout, |x| x.dimmed())
+                    );
+                }
+                entries.push(e)
+            }
+            Err(e) => report.error(pkg, e),
+        }
+    }
+
+    for msg in unmatchedpins(&meta, &entries, &pkgdata.pairs) {
+        report.error(pkg, msg);
+    }
+
+    let nodata = PkgData::default();
+    let resolved = resolve(&meta, &entries, pkg, &pkgdata.pairs);
+    for r in &resolved {
+        let msg = format!("{}: {}", metaid(&r.meta), r.explanation);
+        if r.desktop.is_some() {
+            println!("{pkg}: {}", msg.if_supports_color(Stdout, |x| x.dimmed()));
+        } else if r.isdesktop {
+            println!("{pkg}: {}", msg.if_supports_color(Stdout, |x| x.yellow()));
+        }
+        report.pairing.push(msg);
+    }
+
+    let metapairs = resolved
+        .iter()
+        .filter_map(|r| r.desktop.as_ref().map(|d| (r.meta.clone(), d)))
+        .collect::<Vec<_>>();
+
+    if metapairs.is_empty() && resolved.iter().any(|r| r.isdesktop) {
+        report.error(pkg, "No metapair found".to_string());
+    }
+
+    let components = resolved
+        .iter()
+        .filter(|r| r.desktop.is_some() || !r.isdesktop)
+        .map(|r| (r.meta.as_str(), r.desktop.as_ref()))
+        .collect::<Vec<_>>();
+    let primary = primarycomponent(&components, pkg);
+
+    // Custom data describes a single component, so it only applies when there is exactly one pair
+    let single = metapairs.len() == 1;
+    for (m, d) in &metapairs {
+        let res = xmlparse(
             &path,
-            meta[0].to_string(),
-            desktops[0].to_string(),
+            m.to_string(),
+            d,
+            primary.as_deref() == Some(m.as_str()),
             pkg,
-            &pkgdata,
+            if single { &pkgdata } else { &nodata },
+            opts,
+            report,
         );
-    } else {
-        let mut metapairs: Vec<(String, String)> = Vec::new();
-        for m in &meta {
-            if let Ok(f) = fs::read_to_string(&m) {
-                if let Ok(x) = Element::parse(f.as_bytes()) {
-                    if x.attributes.get("type") == Some(&String::from("desktop"))
-                        || x.attributes.get("type") == Some(&String::from("desktop-application"))
-                        || {
-                            if let Some(y) = x.get_child("id") {
-                                y.attributes.get("type") == Some(&String::from("desktop"))
-                            } else {
-                                false
-                            }
-                        }
-                        || {
-                            if let Some(y) = x.get_child("id") {
-                                y.attributes.get("type")
-                                    == Some(&String::from("desktop-application"))
-                            } else {
-                                false
-                            }
-                        }
-                    {
-                        let id = m
-                            .split('/')
-                            .last()
-                            .unwrap()
-                            .replace(".appdata", "")
-                            .replace(".metainfo", "")
-                            .replace(".xml", "");
-
-                        let deskpfx = format!("{}/share/applications/", path);
-                        let mut desktopfile = String::new();
-                        if let Some(idattr) = x.get_child("id") {
-                            let d = idattr.children.get(0).unwrap().as_text().unwrap();
-                            if d.contains(".desktop")
-                                && desktops.contains(&format!("{deskpfx}{}", d))
-                            {
-                                desktopfile = d.to_string();
-                            }
-                        }
-
-                        if desktopfile.is_empty()
-                            && desktops.contains(&format!("{deskpfx}{}.desktop", id))
-                        {
-                            desktopfile = format!("{}.desktop", id);
-                        }
+        record(pkg, res, report);
+    }
+    for r in resolved {
+        if r.desktop.is_none() && !r.isdesktop {
+            let isprimary = primary.as_deref() == Some(r.meta.as_str());
+            record(
+                pkg,
+                xmlparse_nondesktop(&path, r.meta, isprimary, pkg, opts, report),
+                report,
+            );
+        }
+    }
+}
 
-                        if desktopfile.is_empty()
-                            && desktops.contains(&format!("{deskpfx}{}.desktop", pkg))
-                        {
-                            desktopfile = format!("{}.desktop", pkg);
-                        }
+/// Metainfo file of the component that provides every binary and D-Bus service of the package: the
+/// only component, the only desktop application, or the one named after the package. `components`
+/// are the metainfo files that are generated, with their desktop files.
+fn primarycomponent(components: &[(&str, Option<&DesktopEntry>)], pkg: &str) -> Option<String> {
+    if let [(m, _)] = components {
+        return Some(m.to_string());
+    }
+    let desktops = components
+        .iter()
+        .filter(|(_, d)| d.is_some())
+        .collect::<Vec<_>>();
+    if let [(m, _)] = desktops[..] {
+        return Some(m.to_string());
+    }
+    components
+        .iter()
+        .find(|(m, d)| {
+            let id = metaid(m);
+            let last = id.split('.').next_back().unwrap_or_default();
+            last.eq_ignore_ascii_case(pkg)
+                || d.is_some_and(|d| d.filename().trim_end_matches(".desktop") == pkg)
+        })
+        .map(|(m, _)| m.to_string())
+}
 
-                        if desktopfile.is_empty() {
-                            let mut filtered = desktops
-                                .iter()
-                                .filter(|x| x.to_lowercase().contains(&id.to_lowercase()))
-                                .collect::<Vec<_>>();
-                            if filtered.len() == 1 {
-                                desktopfile = filtered[0].to_string();
-                            } else {
-                                filtered = desktops
-                                    .iter()
-                                    .filter(|x| x.contains("org") || x.contains("com"))
-                                    .collect::<Vec<_>>();
-                                if filtered.len() == 1 {
-                                    desktopfile = filtered[0].to_string();
-                                }
-                            }
-                        }
+/// Component id derived from a metainfo file name
+fn metaid(meta: &str) -> String {
+    meta.split('/')
+        .next_back()
+        .unwrap_or_default()
+        .replace(".appdata", "")
+        .replace(".metainfo", "")
+        .replace(".xml", "")
+}
 
-                        if desktopfile.is_empty() {
-                            continue;
-                        } else {
-                            metapairs.push((m.to_string(), format!("{deskpfx}{desktopfile}")));
-                        }
-                    }
+/// Reads a metainfo file. Files that only fail to parse because of invalid entities are parsed
+/// again with the entities fixed.
+pub(crate) fn readcomponent(meta: &str, changes: &mut Changes) -> Result<Element, String> {
+    let f = fs::read_to_string(meta).map_err(|e| format!("{meta}: {e}"))?;
+    let mut x = parse(f.as_bytes())
+        .or_else(|e| {
+            let mut fixed = Changes::default();
+            let f = fixentities(&f, &mut fixed);
+            match parse(f.as_bytes()) {
+                Ok(x) if !fixed.is_empty() => {
+                    changes.merge(fixed);
+                    Ok(x)
                 }
+                _ => Err(e),
             }
-        }
+        })
+        .map_err(|e| match e {
+            ParseError::MalformedXml(e) => format!("Failed to parse XML: {meta}: {}", e.msg()),
+            e => format!("Failed to parse XML: {meta}: {e:?}"),
+        })?;
+    fixlangattrs(&mut x);
+    Ok(x)
+}
 
-        if metapairs.is_empty() {
-            println!(
-                "{} No metapair found for package {pkg}",
-                "error:".if_supports_color(Stdout, |x| x.red())
-            );
-            //std::process::exit(1);
-        } else {
-            if metapairs.len() == 1 {
-                xmlparse(
-                    &path,
-                    metapairs[0].0.to_string(),
-                    metapairs[0].1.to_string(),
-                    pkg,
-                    &pkgdata,
-                );
-            } else {
-                for (m, d) in &metapairs {
-                    xmlparse(
-                        &path,
-                        m.to_string(),
-                        d.to_string(),
-                        pkg,
-                        &PkgData::default(),
-                    );
-                }
-            }
-            for m in meta {
-                if !metapairs.iter().any(|(x, _)| x == &m) {
-                    xmlparse_nondesktop(m, pkg);
-                }
-            }
-        }
+/// Sanitizes the text of the component and writes it to `output/metadata/{pkg}::{id}.xml`
+fn writecomponent(
+    x: &mut Element,
+    pkg: &str,
+    id: &str,
+    mut changes: Changes,
+    report: &mut PkgReport,
+) -> Result<(), String> {
+    sanitize(x, &mut changes);
+    if !changes.is_empty() {
+        let msg = format!("{id}: sanitized text ({changes})");
+        println!("{pkg}: {}", msg.if_supports_color(Stdout, |x| x.bright_purple()));
+        report.sanitized.push(msg);
     }
+
+    let outpath = format!("output/metadata/{pkg}::{id}.xml");
+    let writer = xmltree::EmitterConfig::new().perform_indent(true);
+    let file = File::create(&outpath).map_err(|e| format!("{outpath}: {e}"))?;
+    x.write_with_config(file, writer)
+        .map_err(|e| format!("{outpath}: {e}"))
 }
 
-pub fn xmlparse(path: &str, meta: String, desktop: String, pkg: &str, pkgdata: &PkgData) {
-    let f = fs::read_to_string(&meta).unwrap();
-    let mut x = match Element::parse(f.as_bytes()) {
-        Ok(x) => x,
-        Err(_) => {
-            println!(
-                "{pkg}: {}: {meta}",
-                "FAILED TO PARSE XML".if_supports_color(Stdout, |x| x.bright_red())
-            );
-            return;
-        }
+/// Replaces the `<pkgname>` of the component with `pkg`
+fn setpkgname(x: &mut Element, pkg: &str) {
+    while x.take_child("pkgname").is_some() {}
+    x.children
+        .insert(0, XMLNode::Element(textelement("pkgname", pkg)));
+}
+
+/// Adds `<name>` and `<summary>` with their translations from the `Name` and `Comment` keys of the
+/// desktop file when the metainfo file does not have them
+fn fromdesktop(x: &mut Element, desktop: &DesktopEntry) {
+    let main = match desktop.main() {
+        Some(m) => m,
+        None => return,
     };
+    for (tag, key) in [("name", "Name"), ("summary", "Comment")] {
+        if x.get_child(tag).is_some() {
+            continue;
+        }
+        if let Some(v) = main.get(key) {
+            x.children.push(XMLNode::Element(textelement(tag, &v)));
+        }
+        for (l, v) in main.translations(key) {
+            let mut e = textelement(tag, &v);
+            e.attributes.insert(LANG.to_string(), l);
+            x.children.push(XMLNode::Element(e));
+        }
+    }
+}
 
-    if x.name == "application" {
-        x.name = "component".to_string();
-        x.attributes
-            .insert("type".to_string(), "desktop-application".to_string());
-    } else if x.name != "component" {
-        println!(
-            "{pkg}: {}: {meta}",
-            "Not a component or application".if_supports_color(Stdout, |x| x.red())
-        );
-        return;
+/// Generates a desktop component from a metainfo file and its desktop file. Returns the output id.
+/// Icon failures are recorded in `report` without failing the component. Only the `primary`
+/// component of a package provides all of its binaries and D-Bus services.
+#[allow(clippy::too_many_arguments)]
+pub fn xmlparse(
+    path: &str,
+    meta: String,
+    desktop: &DesktopEntry,
+    primary: bool,
+    pkg: &str,
+    pkgdata: &PkgData,
+    opts: &GenOptions,
+    report: &mut PkgReport,
+) -> Result<String, String> {
+    let mut changes = Changes::default();
+    let mut x = readcomponent(&meta, &mut changes)?;
+    modernize(&mut x);
+
+    if x.name != "component" {
+        return Err(format!("Not a component or application: {meta}"));
     }
 
-    let mut icondata: Vec<String> = vec![];
-    if let Some(i) = &pkgdata.icon {
-        let ipath = format!("tmp/{}", i.split('/').last().unwrap());
-        dl(i, &ipath);
+    let id = if let Some(customidout) = &pkgdata.outputmetainfo {
+        customidout.replace(".xml", "")
+    } else {
+        metaid(&meta)
+    };
 
+    let policy = opts.iconpolicy;
+    let render = policy.cached == Policy::Generate || policy.remote == Policy::Generate;
+    let name = desktop.get("Icon").filter(|i| !i.is_empty());
+    let icondata = if !render {
+        vec![]
+    } else if let Some(i) = &pkgdata.icon {
         let iout = if let Some(customiconpath) = &pkgdata.outputicon {
             customiconpath.to_string()
         } else {
-            format!(
-                "{}.png",
-                meta.split('/')
-                    .last()
-                    .unwrap()
-                    .replace(".appdata", "")
-                    .replace(".metainfo", "")
-                    .replace(".xml", "")
-            )
-
-            //format!("{}.png", i.split('/').last().unwrap().replace(".png", "").replace(".jpg", "").replace(".svg", ""))
+            opts.iconnames.file(&format!("{}.png", metaid(&meta)), &id, pkg)
         };
-
-        let mut dlicon = |size: u32| {
-            match Command::new("convert")
-                .arg("-size")
-                .arg(format!("{}x{}", size, size))
-                .arg("xc:none")
-                .arg("-background")
-                .arg("none")
-                .arg(&ipath)
-                .arg("-gravity")
-                .arg("center")
-                .arg("-composite")
-                .arg(format!("output/icons/{size}x{size}/{iout}"))
-                .output()
-            {
-                Ok(x) => {
-                    if !x.status.success() {
-                        println!("{}", String::from_utf8_lossy(&x.stderr))
-                    }
-                }
-                Err(e) => println!("{}", e),
-            }
-            icondata.push(format!(
-                "<icon type=\"cached\" width=\"{size}\" height=\"{size}\">{iout}</icon>"
-            ));
+        dlicons(i, &iout, "output", pkg, report)
+    } else if let Some(i) = &name {
+        let iout = if let Some(customiconpath) = &pkgdata.outputicon {
+            customiconpath.to_string()
+        } else {
+            opts.iconnames.file(&format!("{i}.png"), &id, pkg)
         };
-
-        dlicon(64);
-        dlicon(128);
+        findicons(path, i, &iout, opts.maxupscale, "output", pkg, report)
     } else {
-        let mut icon = None;
-        if let Ok(file) = File::open(&desktop) {
-            let reader = BufReader::new(file);
-            for line in reader.lines().flatten() {
-                if line.get(..5) == Some("Icon=") {
-                    if let Some(x) = line.split('=').nth(1) {
-                        icon = Some(x.to_string());
-                    }
-                    break;
-                }
-            }
-        }
-
-        if let Some(i) = icon {
-            let pathscalable = format!("{}/share/icons/hicolor/scalable/apps/{}.svg", path, i);
-            let iout = if let Some(customiconpath) = &pkgdata.outputicon {
-                customiconpath.to_string()
-            } else {
-                format!("{i}.png")
-            };
-            let mut addicon = |size: u32| {
-                let iconpath = format!("{}/share/icons/hicolor/{size}x{size}/apps/{}", path, iout);
-                if Path::new(&iconpath).exists()
-                    && fs::copy(&iconpath, format!("output/icons/{size}x{size}/{iout}")).is_ok()
-                {
-                    fs::set_permissions(
-                        &format!("output/icons/{size}x{size}/{iout}"),
-                        fs::Permissions::from_mode(0o644),
-                    )
-                    .unwrap();
-                    icondata.push(format!(
-                        "<icon type=\"cached\" width=\"{size}\" height=\"{size}\">{iout}</icon>"
-                    ));
-                } else if Path::new(&iconpath.replace(".png", ".svg")).exists() {
-                    match Command::new("convert")
-                        .arg("-size")
-                        .arg(format!("{}x{}", size, size))
-                        .arg("xc:none")
-                        .arg("-background")
-                        .arg("none")
-                        .arg(&iconpath.replace(".png", ".svg"))
-                        .arg("-gravity")
-                        .arg("center")
-                        .arg("-composite")
-                        .arg(format!("output/icons/{size}x{size}/{iout}"))
-                        .output()
-                    {
-                        Ok(x) => {
-                            if !x.status.success() {
-                                println!("{}", String::from_utf8_lossy(&x.stderr))
-                            }
-                        }
-                        Err(e) => println!("{}", e),
-                    }
-                    icondata.push(format!(
-                        "<icon type=\"cached\" width=\"{size}\" height=\"{size}\">{iout}</icon>"
-                    ));
-                } else if Path::new(&pathscalable).exists() {
-                    match Command::new("convert")
-                        .arg("-size")
-                        .arg(format!("{}x{}", size, size))
-                        .arg("xc:none")
-                        .arg("-background")
-                        .arg("none")
-                        .arg(&pathscalable)
-                        .arg("-gravity")
-                        .arg("center")
-                        .arg("-composite")
-                        .arg(format!("output/icons/{size}x{size}/{iout}"))
-                        .output()
-                    {
-                        Ok(x) => {
-                            if !x.status.success() {
-                                println!("{}", String::from_utf8_lossy(&x.stderr))
-                            }
-                        }
-                        Err(e) => println!("{}", e),
-                    }
-                    icondata.push(format!(
-                        "<icon type=\"cached\" width=\"{size}\" height=\"{size}\">{iout}</icon>"
-                    ));
-                } else {
-                    let sizes = vec![64, 72, 96, 128, 192, 256, 512, 1024]
-                        .into_iter()
-                        .filter(|x| *x >= size)
-                        .collect::<Vec<u32>>();
-                    let mut scalepath = String::new();
-                    for s in &sizes {
-                        if Path::new(&format!(
-                            "{}/share/icons/hicolor/{s}x{s}/apps/{}.png",
-                            path, i
-                        ))
-                        .exists()
-                        {
-                            //scalesize = s;
-                            scalepath =
-                                format!("{}/share/icons/hicolor/{s}x{s}/apps/{}.png", path, i);
-                            break;
-                        }
-                    }
-
-                    if !scalepath.is_empty() {
-                        let img = image::open(&scalepath).unwrap();
-                        let newimg = img.resize(size, size, image::imageops::Lanczos3);
-                        newimg
-                            .save(format!("output/icons/{size}x{size}/{iout}"))
-                            .unwrap();
-                        icondata.push(format!(
-                            "<icon type=\"cached\" width=\"{size}\" height=\"{size}\">{iout}</icon>"
-                        ));
-                    } else {
-                        for s in &sizes {
-                            if Path::new(&format!(
-                                "{}/share/icons/hicolor/{s}x{s}/apps/{}.svg",
-                                path, i
-                            ))
-                            .exists()
-                            {
-                                // scalesize = s;
-                                scalepath =
-                                    format!("{}/share/icons/hicolor/{s}x{s}/apps/{}.svg", path, i);
-                                break;
-                            }
-                        }
+        vec![]
+    };
 
-                        if !scalepath.is_empty() {
-                            match Command::new("convert")
-                                .arg("-size")
-                                .arg(format!("{}x{}", size, size))
-                                .arg("xc:none")
-                                .arg("-background")
-                                .arg("none")
-                                .arg(&scalepath)
-                                .arg("-gravity")
-                                .arg("center")
-                                .arg("-composite")
-                                .arg(format!("output/icons/{size}x{size}/{iout}"))
-                                .output()
-                            {
-                                Ok(x) => {
-                                    if !x.status.success() {
-                                        println!("{}", String::from_utf8_lossy(&x.stderr))
-                                    }
-                                }
-                                Err(e) => println!("{}", e),
-                            }
-                            icondata.push(format!(
-                                "<icon type=\"cached\" width=\"{size}\" height=\"{size}\">{iout}</icon>"
-                            ));
-                        }
-                    }
-                }
-            };
-            addicon(128);
-            addicon(64);
+    x.children.retain(|c| match c.as_element() {
+        Some(e) if e.name == "icon" => {
+            policy.get(e.attributes.get("type").map(|t| t.as_str()).unwrap_or_default()) == Policy::Keep
         }
-    }
-
-    let pkgname = format!("<pkgname>{}</pkgname>", pkg);
-    let launchdata = format!(
-        "<launchable type=\"desktop-id\">{}</launchable>",
-        desktop.split('/').last().unwrap()
-    );
-
-    let p = Element::parse(pkgname.as_bytes()).unwrap();
-    let l = Element::parse(launchdata.as_bytes()).unwrap();
+        _ => true,
+    });
 
-    while x.get_child("icon").is_some() {
-        x.take_child("icon").unwrap();
-    }
-
-    if icondata.is_empty() {
+    if render && icondata.is_empty() {
         println!(
             "{pkg}: {}",
             "no desktop icons found".if_supports_color(Stdout, |x| x.bright_purple())
         );
-    } else if icondata.len() == 1 {
+    } else if render && icondata.len() == 1 {
         println!(
             "{pkg}: {}",
             "some desktop icons missing".if_supports_color(Stdout, |x| x.bright_purple())
         );
     }
 
-    for data in icondata {
-        let d = Element::parse(data.as_bytes()).unwrap();
-        x.children.insert(0, xmltree::XMLNode::Element(d));
+    let mut icons = vec![];
+    if let (Policy::Generate, Some(i)) = (policy.local, &name) {
+        icons.extend(localicons(path, i));
+    }
+    if let (Policy::Generate, Some(mediaurl)) = (policy.remote, &opts.mediaurl) {
+        let keep = policy.cached == Policy::Generate;
+        icons.extend(remoteicons(&icondata, &id, mediaurl, keep, "output", pkg, report));
+    }
+    if policy.cached == Policy::Generate {
+        icons.extend(icondata);
+    }
+    if let (Policy::Generate, Some(i)) = (policy.stock, &name) {
+        if !i.starts_with('/') {
+            icons.push(stockicon(i));
+        }
+    }
+    for d in icons {
+        x.children.insert(0, XMLNode::Element(d));
     }
 
     if x.get_child("launchable").is_none() {
-        x.children.insert(0, xmltree::XMLNode::Element(l));
+        let mut l = textelement("launchable", desktop.filename());
+        l.attributes
+            .insert("type".to_string(), "desktop-id".to_string());
+        x.children.insert(0, XMLNode::Element(l));
     }
 
     if let Some(customid) = &pkgdata.id {
-        x.take_child("id").unwrap();
-        x.children.insert(
-            0,
-            xmltree::XMLNode::Element(
-                Element::parse(format!("<id>{customid}</id>").as_bytes()).unwrap(),
-            ),
-        );
+        x.take_child("id");
+        x.children
+            .insert(0, XMLNode::Element(textelement("id", customid)));
     }
 
-    if x.get_child("pkgname").is_none() {
-        x.children.insert(0, xmltree::XMLNode::Element(p));
-    } else {
-        x.take_child("pkgname").unwrap();
-        x.children.insert(0, xmltree::XMLNode::Element(p));
-    }
+    fromdesktop(&mut x, desktop);
+    autoprovides(path, &mut x, Some(desktop), primary);
+    report.addlanguages(addlanguages(path, &mut x));
+    setpkgname(&mut x, pkg);
+    normalize(&mut x);
 
-    // Fix description field translations
-    let mut desc_children = x
-        .children
-        .iter()
-        .filter(|x| {
-            if let Some(y) = x.as_element() {
-                y.name == "description"
-            } else {
-                false
-            }
-        })
-        .collect::<Vec<_>>();
-    if desc_children.len() == 1 {
-        if let Some(d) = desc_children.get_mut(0) {
-            if let Some(d) = d.clone().as_mut_element() {
-                fixtranslation(d, &mut x);
-            }
-        }
+    if let Some(mediaurl) = &opts.mediaurl {
+        mirrorscreenshots(&mut x, &id, pkg, mediaurl, "output", CACHEDIR);
     }
 
-    let writer = xmltree::EmitterConfig::new().perform_indent(true);
-    let id = if let Some(customidout) = &pkgdata.outputmetainfo {
-        customidout.replace(".xml", "")
-    } else {
-        meta.split('/')
-            .last()
-            .unwrap()
-            .replace(".appdata", "")
-            .replace(".metainfo", "")
-            .replace(".xml", "")
-    };
-
-    match x.write_with_config(
-        File::create(format!("output/metadata/{pkg}::{id}.xml")).unwrap(),
-        writer,
-    ) {
-        Ok(_) => {
-            match Command::new("sed")
-                .arg("-i")
-                .arg(r#"s/\xe2\x80\x8b//g"#)
-                .arg(&format!("output/metadata/{pkg}::{id}.xml"))
-                .output()
-            {
-                Ok(_) => {
-                    println!(
-                        "{pkg}: {}: {}",
-                        id.if_supports_color(Stdout, |x| x.cyan()),
-                        "Success!".if_supports_color(Stdout, |x| x.green())
-                    );
-                }
-                Err(e) => {
-                    println!("{pkg}: {}", e.if_supports_color(Stdout, |x| x.red()));
-                }
-            }
-        }
-        Err(e) => println!("{pkg}: {}", e.if_supports_color(Stdout, |x| x.red())),
-    }
+    writecomponent(&mut x, pkg, &id, changes, report)?;
+    println!(
+        "{pkg}: {}: {}",
+        id.if_supports_color(Stdout, |x| x.cyan()),
+        "Success!".if_supports_color(Stdout, |x| x.green())
+    );
+    Ok(id)
 }
 
-pub fn xmlparse_nondesktop(meta: String, pkg: &str) {
-    let f = fs::read_to_string(&meta).unwrap();
-    let mut x = match Element::parse(f.as_bytes()) {
-        Ok(x) => x,
-        Err(_) => {
-            println!(
-                "{pkg}: {}: {meta}",
-                "FAILED TO PARSE XML".if_supports_color(Stdout, |x| x.bright_green())
-            );
-            return;
-        }
-    };
+/// Generates a component without a desktop file, such as an addon, font or console application.
+/// Returns the output id.
+pub fn xmlparse_nondesktop(
+    path: &str,
+    meta: String,
+    primary: bool,
+    pkg: &str,
+    opts: &GenOptions,
+    report: &mut PkgReport,
+) -> Result<String, String> {
+    let mut changes = Changes::default();
+    let mut x = readcomponent(&meta, &mut changes)?;
+    modernize(&mut x);
 
     if x.name != "component" {
-        println!(
-            "{pkg}: {}: {meta}",
-            "Not a component".if_supports_color(Stdout, |x| x.red())
-        );
-        return;
+        return Err(format!("Not a component: {meta}"));
     }
 
-    let pkgdata = format!("<pkgname>{}</pkgname>", pkg);
+    let id = metaid(&meta);
+    let kind = fixnondesktop(path, &mut x, primary, pkg, &id, opts, report).map_err(|e| format!("{id}: {e}"))?;
+    report.addlanguages(addlanguages(path, &mut x));
 
-    let p = Element::parse(pkgdata.as_bytes()).unwrap();
+    setpkgname(&mut x, pkg);
+    normalize(&mut x);
 
-    if x.get_child("pkgname").is_none() {
-        x.children.insert(0, xmltree::XMLNode::Element(p));
-    } else {
-        x.take_child("pkgname").unwrap();
-        x.children.insert(0, xmltree::XMLNode::Element(p));
+    if let Some(mediaurl) = &opts.mediaurl {
+        mirrorscreenshots(&mut x, &id, pkg, mediaurl, "output", CACHEDIR);
     }
 
-    // Fix description field translations
-    let mut desc_children = x
-        .children
-        .iter()
-        .filter(|x| {
-            if let Some(y) = x.as_element() {
-                y.name == "description"
-            } else {
-                false
-            }
-        })
-        .collect::<Vec<_>>();
-    if desc_children.len() == 1 {
-        if let Some(d) = desc_children.get_mut(0) {
-            if let Some(d) = d.clone().as_mut_element() {
-                fixtranslation(d, &mut x);
-            }
-        }
-    }
-
-    let writer = xmltree::EmitterConfig::new().perform_indent(true);
-    let id = meta
-        .split('/')
-        .last()
-        .unwrap()
-        .replace(".appdata", "")
-        .replace(".metainfo", "")
-        .replace(".xml", "");
-    match x.write_with_config(
-        File::create(format!("output/metadata/{pkg}::{id}.xml")).unwrap(),
-        writer,
-    ) {
-        Ok(_) => {
-            match Command::new("sed")
-                .arg("-i")
-                .arg(r#"s/\xe2\x80\x8b//g"#)
-                .arg(&format!("output/metadata/{pkg}::{id}.xml"))
-                .output()
-            {
-                Ok(_) => {
-                    println!(
-                        "{pkg}: {}: {}",
-                        id.if_supports_color(Stdout, |x| x.cyan()),
-                        "Addon success!".if_supports_color(Stdout, |x| x.green())
-                    );
-                }
-                Err(e) => {
-                    println!("{pkg}: {}", e.if_supports_color(Stdout, |x| x.red()));
-                }
-            }
-        }
-        Err(e) => println!("{pkg}: {}", e.if_supports_color(Stdout, |x| x.red())),
-    }
+    writecomponent(&mut x, pkg, &id, changes, report)?;
+    println!(
+        "{pkg}: {}: {}",
+        id.if_supports_color(Stdout, |x| x.cyan()),
+        format!("{kind} success!").if_supports_color(Stdout, |x| x.green())
+    );
+    Ok(id)
 }
-
-fn fixtranslation(d: &mut Element, x: &mut Element) {
-    let n = &d.name.to_string();
-    let mut map: HashMap<String, Vec<xmltree::Element>> = HashMap::new();
-    for p in d.clone().children {
-        if let Some(p) = p.as_element() {
-            if p.children.iter().any(|x| x.as_element().is_some()) {
-                let mut p = p.clone();
-                fixtranslation(&mut p, d);
-            }
-        }
-    }
-    for p in &d.children {
-        if let Some(p) = p.as_element() {
-            let mut p2 = p.clone();
-            p2.attributes.clear();
-            if let Some(l) = p.attributes.get("lang") {
-                if let Some(v) = map.get_mut(l) {
-                    v.push(p2.clone());
-                } else {
-                    map.insert(l.clone(), vec![p2.clone()]);
-                }
-            } else if let Some(v) = map.get_mut("") {
-                v.push(p2.clone());
-            } else {
-                map.insert("".to_string(), vec![p2.clone()]);
-            }
-        }
-    }
-    let i = x.children.iter().position(|x| if let Some(y) = x.as_element() { y.eq(d) } else { false }).unwrap_or_default();
-    x.take_child(n.as_str()).unwrap();
-    let mut mapvec = map.into_iter().collect::<Vec<_>>();
-    // Reverse order
-    mapvec.sort_by(|(x, _), (y, _)| y.cmp(x));
-    for (k, v) in mapvec {
-        let mut d = Element::parse(format!("<{n}></{n}>").as_bytes()).unwrap();
-        if !k.is_empty() {
-            d.attributes.insert("lang".to_string(), k);
-        }
-        for p in v.into_iter().rev() {
-            d.children.insert(0, xmltree::XMLNode::Element(p));
-        }
-        x.children.insert(i, xmltree::XMLNode::Element(d));
-    }
-}
\ No newline at end of file
@@ -0,0 +1,208 @@
+use owo_colors::{OwoColorize, Stream::Stdout};
+use std::{fmt, fs, path::Path, process::Command, str::FromStr};
+use sysinfo::{Disk, DiskExt};
+
+/// Directory holding the temporary GC roots of packages that are currently being processed
+pub const ROOTDIR: &str = "tmp/gcroots";
+
+/// A temporary GC root keeping the build outputs of a package alive while it is processed.
+/// The root is released when this is dropped.
+#[derive(Debug)]
+pub struct GcRoot {
+    link: String,
+}
+
+impl GcRoot {
+    pub fn new(pkg: &str) -> Self {
+        if let Err(e) = fs::create_dir_all(ROOTDIR) {
+            println!("{} {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+        }
+        GcRoot {
+            link: format!("{ROOTDIR}/{pkg}"),
+        }
+    }
+
+    /// Out link to pass to `nix-build -o`. Additional outputs get a `-{output}` suffix
+    pub fn link(&self) -> &str {
+        &self.link
+    }
+}
+
+impl Drop for GcRoot {
+    fn drop(&mut self) {
+        let name = match Path::new(&self.link).file_name() {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => return,
+        };
+        if let Ok(entries) = fs::read_dir(ROOTDIR) {
+            for entry in entries.flatten() {
+                let f = entry.file_name().to_string_lossy().to_string();
+                if f == name || f.starts_with(&format!("{name}-")) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+    }
+}
+
+/// An amount of disk space, either absolute or relative to the size of the watched filesystem
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Bytes(u64),
+    Percent(f64),
+}
+
+impl Threshold {
+    pub fn bytes(&self, total: u64) -> u64 {
+        match self {
+            Threshold::Bytes(b) => *b,
+            Threshold::Percent(p) => (total as f64 * p / 100.0) as u64,
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    /// Accepts plain byte counts, sizes with a K, M, G or T suffix (powers of 1024) or percentages
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(p) = s.strip_suffix('%') {
+            let p = p
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid percentage '{s}'"))?;
+            if !(0.0..=100.0).contains(&p) {
+                return Err(format!("percentage '{s}' is not between 0 and 100"));
+            }
+            return Ok(Threshold::Percent(p));
+        }
+
+        let upper = s.to_uppercase();
+        let digits = upper.trim_end_matches('B').trim_end_matches('I');
+        let (num, mult) = match digits.chars().last() {
+            Some('K') => (&digits[..digits.len() - 1], 1u64 << 10),
+            Some('M') => (&digits[..digits.len() - 1], 1u64 << 20),
+            Some('G') => (&digits[..digits.len() - 1], 1u64 << 30),
+            Some('T') => (&digits[..digits.len() - 1], 1u64 << 40),
+            _ => (digits, 1),
+        };
+        let num = num
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("invalid size '{s}'"))?;
+        if num < 0.0 {
+            return Err(format!("size '{s}' is negative"));
+        }
+        Ok(Threshold::Bytes((num * mult as f64) as u64))
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Threshold::Bytes(b) => write!(f, "{}", humansize(*b)),
+            Threshold::Percent(p) => write!(f, "{p}%"),
+        }
+    }
+}
+
+pub fn humansize(b: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = b as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// When and how much to garbage collect the nix store during a run
+#[derive(Debug, Clone)]
+pub struct GcPolicy {
+    /// Collect when free space on the watched filesystem drops below this
+    pub minfree: Option<Threshold>,
+    /// Collect when used space on the watched filesystem grows beyond this
+    pub maxused: Option<Threshold>,
+    /// Collect once the run used this much of the space that was free when it started
+    pub maxgrowth: Option<Threshold>,
+    /// Extra space freed on top of what is needed to satisfy the thresholds
+    pub headroom: Threshold,
+}
+
+impl Default for GcPolicy {
+    /// Collects after every 50 GiB the run uses, so the policy does not depend on the size or fill
+    /// level of the filesystem
+    fn default() -> Self {
+        GcPolicy {
+            minfree: None,
+            maxused: None,
+            maxgrowth: Some(Threshold::Bytes(50 << 30)),
+            headroom: Threshold::Bytes(10 << 30),
+        }
+    }
+}
+
+impl GcPolicy {
+    /// Returns how many bytes should be freed to bring `disk` back within the policy, or `None` if
+    /// no collection is needed. `startfree` is the free space when the run started.
+    pub fn needed(&self, disk: &Disk, startfree: u64) -> Option<u64> {
+        let total = disk.total_space();
+        let available = disk.available_space();
+        let used = total.saturating_sub(available);
+        let grown = startfree.saturating_sub(available);
+
+        let mut deficit = 0;
+        if let Some(minfree) = self.minfree {
+            deficit = deficit.max(minfree.bytes(total).saturating_sub(available));
+        }
+        if let Some(maxused) = self.maxused {
+            deficit = deficit.max(used.saturating_sub(maxused.bytes(total)));
+        }
+        if let Some(maxgrowth) = self.maxgrowth {
+            deficit = deficit.max(grown.saturating_sub(maxgrowth.bytes(total)));
+        }
+
+        if deficit == 0 {
+            None
+        } else {
+            Some(deficit + self.headroom.bytes(total))
+        }
+    }
+}
+
+/// Finds the disk whose mount point contains `path`, preferring the most specific mount
+pub fn finddisk<'a>(disks: &'a mut [Disk], path: &Path) -> Option<&'a mut Disk> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    disks
+        .iter_mut()
+        .filter(|d| path.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().components().count())
+}
+
+/// Runs `nix-store --gc`, limited to `maxfreed` bytes if given
+pub fn collect(maxfreed: Option<u64>) {
+    let mut cmd = Command::new("nix-store");
+    cmd.arg("--gc");
+    if let Some(m) = maxfreed {
+        cmd.arg("--max-freed").arg(m.to_string());
+        println!(
+            "{} (freeing up to {})",
+            "Cleaning nix store...".if_supports_color(Stdout, |x| x.purple()),
+            humansize(m)
+        );
+    } else {
+        println!(
+            "{}",
+            "Cleaning nix store...".if_supports_color(Stdout, |x| x.purple())
+        );
+    }
+    match cmd.output() {
+        Ok(x) if x.status.success() => (),
+        _ => println!(
+            "{}",
+            "Could not run nix-store --gc".if_supports_color(Stdout, |x| x.red())
+        ),
+    }
+}
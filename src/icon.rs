@@ -0,0 +1,490 @@
+use crate::{
+    download::dl,
+    find::textelement,
+    report::{CachedIcon, PkgReport},
+    reproducible::sha256,
+};
+use std::{fs, os::unix::prelude::PermissionsExt, path::Path, process::Command, str::FromStr};
+use xmltree::Element;
+
+/// How cached icon files are named
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IconNames {
+    /// After the icon, so packages using the same icon name share the file
+    #[default]
+    Icon,
+    /// After the icon, prefixed with the component id
+    Component,
+    /// After the icon, prefixed with the package attribute
+    Package,
+}
+
+impl FromStr for IconNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "icon" => Ok(IconNames::Icon),
+            "component" => Ok(IconNames::Component),
+            "package" => Ok(IconNames::Package),
+            _ => Err(format!(
+                "unknown icon naming `{s}`, expected icon, component or package"
+            )),
+        }
+    }
+}
+
+impl IconNames {
+    /// File name of the cached icon `file` of component `id` in package `pkg`
+    pub fn file(self, file: &str, id: &str, pkg: &str) -> String {
+        match self {
+            IconNames::Icon => file.to_string(),
+            IconNames::Component => format!("{id}_{file}"),
+            IconNames::Package => format!("{pkg}_{file}"),
+        }
+    }
+}
+
+/// What to do with the icons of one type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Remove upstream icons and generate none
+    Drop,
+    /// Keep upstream icons
+    Keep,
+    /// Replace upstream icons with generated ones
+    Generate,
+}
+
+/// Policy for every icon type of desktop applications
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IconPolicy {
+    pub stock: Policy,
+    pub cached: Policy,
+    pub remote: Policy,
+    pub local: Policy,
+}
+
+impl Default for IconPolicy {
+    fn default() -> Self {
+        IconPolicy {
+            stock: Policy::Drop,
+            cached: Policy::Generate,
+            remote: Policy::Drop,
+            local: Policy::Drop,
+        }
+    }
+}
+
+impl FromStr for IconPolicy {
+    type Err = String;
+
+    /// Parses `TYPE=POLICY` pairs separated by commas, types that are not given keep their default
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut p = IconPolicy::default();
+        for pair in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            let (kind, policy) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `TYPE=POLICY`, got `{pair}`"))?;
+            let policy = match policy {
+                "drop" => Policy::Drop,
+                "keep" => Policy::Keep,
+                "generate" => Policy::Generate,
+                _ => {
+                    return Err(format!(
+                        "unknown icon policy `{policy}`, expected drop, keep or generate"
+                    ))
+                }
+            };
+            match kind {
+                "stock" => p.stock = policy,
+                "cached" => p.cached = policy,
+                "remote" => p.remote = policy,
+                "local" => p.local = policy,
+                _ => {
+                    return Err(format!(
+                        "unknown icon type `{kind}`, expected stock, cached, remote or local"
+                    ))
+                }
+            }
+        }
+        Ok(p)
+    }
+}
+
+impl IconPolicy {
+    /// Policy for the `type` of an `<icon>`
+    pub fn get(&self, kind: &str) -> Policy {
+        match kind {
+            "stock" => self.stock,
+            "cached" => self.cached,
+            "remote" => self.remote,
+            "local" => self.local,
+            _ => Policy::Drop,
+        }
+    }
+}
+
+fn icon(kind: &str, value: &str, size: Option<u32>) -> Element {
+    let mut e = textelement("icon", value);
+    e.attributes.insert("type".to_string(), kind.to_string());
+    if let Some(size) = size {
+        e.attributes.insert("width".to_string(), size.to_string());
+        e.attributes.insert("height".to_string(), size.to_string());
+    }
+    e
+}
+
+pub fn stockicon(name: &str) -> Element {
+    icon("stock", name, None)
+}
+
+/// Local icons for the theme icon `name` in the store path `path`, or for `name` itself if it is an
+/// absolute path
+pub fn localicons(path: &str, name: &str) -> Vec<Element> {
+    if name.starts_with('/') {
+        return match Path::new(name).is_file() {
+            true => vec![icon("local", name, None)],
+            false => vec![],
+        };
+    }
+    let mut icons = vec![];
+    for size in [64, 128] {
+        let p = format!("{path}/share/icons/hicolor/{size}x{size}/apps/{name}.png");
+        if Path::new(&p).is_file() {
+            icons.push(icon("local", &p, Some(size)));
+        }
+    }
+    let p = format!("{path}/share/icons/hicolor/scalable/apps/{name}.svg");
+    if Path::new(&p).is_file() {
+        icons.push(icon("local", &p, None));
+    }
+    icons
+}
+
+/// Publishes the files of `cached` icons in `{output}/media/{id}/icons` and returns remote icons
+/// pointing at them below `mediaurl`. The cached files are moved unless `keep` is set.
+pub fn remoteicons(
+    cached: &[Element],
+    id: &str,
+    mediaurl: &str,
+    keep: bool,
+    output: &str,
+    pkg: &str,
+    report: &mut PkgReport,
+) -> Vec<Element> {
+    let mut icons = vec![];
+    for c in cached {
+        let (size, file) = match (c.attributes.get("width"), c.get_text()) {
+            (Some(s), Some(f)) => (s.clone(), f.to_string()),
+            _ => continue,
+        };
+        let rel = format!("{id}/icons/{size}x{size}/{file}");
+        let (src, dest) = (
+            format!("{output}/icons/{size}x{size}/{file}"),
+            format!("{output}/media/{rel}"),
+        );
+        let res =
+            fs::create_dir_all(format!("{output}/media/{id}/icons/{size}x{size}")).and_then(|_| {
+                match keep {
+                    true => fs::copy(&src, &dest).map(|_| ()),
+                    false => fs::rename(&src, &dest),
+                }
+            });
+        match res {
+            Ok(_) => {
+                if !keep {
+                    report.icons.remove(&format!("{size}x{size}/{file}"));
+                }
+                icons.push(icon(
+                    "remote",
+                    &format!("{}/{rel}", mediaurl.trim_end_matches('/')),
+                    size.parse().ok(),
+                ))
+            }
+            Err(e) => report.error(pkg, format!("{dest}: {e}")),
+        }
+    }
+    icons
+}
+
+/// Share of pixels that have to be visible for an icon not to count as mostly transparent
+const MINVISIBLE: f32 = 0.05;
+/// Largest ratio between the longer and the shorter side of an icon
+const MAXASPECT: f32 = 1.2;
+
+/// Leading number of an SVG length such as `48`, `48px` or `12.7mm`, `None` for percentages
+fn svglength(s: &str) -> Option<f32> {
+    let s = s.trim();
+    if s.ends_with('%') {
+        return None;
+    }
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    s[..end].parse().ok().filter(|l: &f32| *l > 0.0)
+}
+
+/// Width and height of the image `src`, read from the `width` and `height` or the `viewBox` of the
+/// root element for SVG images. `None` if they can not be found.
+fn sourcedimensions(src: &str) -> Option<(f32, f32)> {
+    if let Ok((w, h)) = image::image_dimensions(src) {
+        return Some((w as f32, h as f32));
+    }
+    let svg = Element::parse(fs::read(src).ok()?.as_slice()).ok()?;
+    let length = |k: &str| svg.attributes.get(k).and_then(|v| svglength(v));
+    if let (Some(w), Some(h)) = (length("width"), length("height")) {
+        return Some((w, h));
+    }
+    let viewbox = svg
+        .attributes
+        .get("viewBox")?
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match viewbox[..] {
+        [_, _, w, h] if w > 0.0 && h > 0.0 => Some((w, h)),
+        _ => None,
+    }
+}
+
+/// Checks that the source of an icon is about square. This has to happen before rendering, as
+/// `convert` centers every source on a square canvas.
+fn checkaspect(src: &str) -> Result<(), String> {
+    match sourcedimensions(src) {
+        Some((w, h)) if w.max(h) > w.min(h) * MAXASPECT => {
+            Err(format!("{src}: wrong aspect ratio {w}x{h}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that a cached icon is not mostly transparent
+fn checkquality(file: &str) -> Result<(), String> {
+    let img = image::open(file)
+        .map_err(|e| format!("{file}: {e}"))?
+        .to_rgba8();
+    let (w, h) = img.dimensions();
+    let visible = img.pixels().filter(|p| p[3] >= 32).count();
+    if (visible as f32) < MINVISIBLE * (w * h) as f32 {
+        return Err(format!(
+            "{file}: mostly transparent ({}% visible)",
+            visible * 100 / (w * h) as usize
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the quality of a cached icon in `output` rendered from `source`, which is `sourcesize`
+/// pixels large unless it is a vector image, and records it with its hash, so packages overwriting
+/// each other's icons are found. Overwriting an icon of another component of the same package is an
+/// error. Returns its `<icon>` element, or `None` if it was rejected and removed.
+fn cached(
+    size: u32,
+    iout: &str,
+    source: &str,
+    sourcesize: Option<u32>,
+    output: &str,
+    pkg: &str,
+    report: &mut PkgReport,
+) -> Option<Element> {
+    let file = format!("{size}x{size}/{iout}");
+    let outpath = format!("{output}/icons/{file}");
+    if let Err(e) = checkquality(&outpath) {
+        let _ = fs::remove_file(&outpath);
+        report.warn(pkg, format!("rejected icon: {e}"));
+        return None;
+    }
+    if let Some(s) = sourcesize.filter(|s| *s < size) {
+        report.warn(pkg, format!("{file}: upscaled from {s}px"));
+    }
+    if let Ok(data) = fs::read(&outpath) {
+        let hash = sha256(&data);
+        if let Some(old) = report.icons.get(&file).filter(|old| old.sha256 != hash) {
+            let msg = format!(
+                "icon collision: {file} from {} was overwritten with {source}",
+                old.source
+            );
+            report.error(pkg, msg);
+        }
+        report.icons.insert(
+            file,
+            CachedIcon {
+                sha256: hash,
+                source: source.to_string(),
+                sourcesize,
+            },
+        );
+    }
+    Some(icon("cached", iout, Some(size)))
+}
+
+/// Renders `src` centered on a transparent `size`x`size` canvas using ImageMagick. Metadata and
+/// timestamps are left out so the output only depends on the input.
+pub fn convert(src: &str, size: u32, out: &str) -> Result<(), String> {
+    match Command::new("convert")
+        .arg("-size")
+        .arg(format!("{}x{}", size, size))
+        .arg("xc:none")
+        .arg("-background")
+        .arg("none")
+        .arg(src)
+        .arg("-gravity")
+        .arg("center")
+        .arg("-composite")
+        .arg("-strip")
+        .arg("-define")
+        .arg("png:exclude-chunks=date,time")
+        .arg(out)
+        .output()
+    {
+        Ok(x) if x.status.success() => Ok(()),
+        Ok(x) => Err(format!(
+            "{src}: {}",
+            String::from_utf8_lossy(&x.stderr).trim()
+        )),
+        Err(e) => Err(format!("convert: {e}")),
+    }
+}
+
+/// Downloads a custom icon from `url` and caches it as `iout` in `{output}/icons`
+pub fn dlicons(
+    url: &str,
+    iout: &str,
+    output: &str,
+    pkg: &str,
+    report: &mut PkgReport,
+) -> Vec<Element> {
+    let mut icondata = vec![];
+    let ipath = format!("tmp/{}", url.split('/').next_back().unwrap_or_default());
+    match dl(url, &ipath) {
+        Ok(_) => {
+            if let Err(e) = checkaspect(&ipath) {
+                report.warn(pkg, format!("rejected icon: {e}"));
+                return icondata;
+            }
+            // Vector images can not be opened and have no size
+            let sourcesize = image::image_dimensions(&ipath).ok().map(|(w, h)| w.max(h));
+            for size in [64, 128] {
+                match convert(
+                    &ipath,
+                    size,
+                    &format!("{output}/icons/{size}x{size}/{iout}"),
+                ) {
+                    Ok(_) => {
+                        icondata.extend(cached(size, iout, url, sourcesize, output, pkg, report))
+                    }
+                    Err(e) => report.error(pkg, e),
+                }
+            }
+        }
+        Err(e) => report.error(pkg, e),
+    }
+    icondata
+}
+
+/// How a cached icon is made from its source
+#[derive(Debug, Clone, Copy)]
+enum Render {
+    /// Copied as it is, for PNG icons of the right size
+    Copy,
+    /// Scaled to the size, for PNG icons of other sizes
+    Resize,
+    /// Rendered with ImageMagick, for vector images
+    Convert,
+}
+
+fn render(src: &str, how: Render, size: u32, out: &str) -> Result<(), String> {
+    match how {
+        Render::Copy => {
+            fs::copy(src, out).map_err(|e| format!("{src}: {e}"))?;
+            fs::set_permissions(out, fs::Permissions::from_mode(0o644))
+                .map_err(|e| format!("{out}: {e}"))
+        }
+        Render::Resize => image::open(src)
+            .map_err(|e| format!("{src}: {e}"))?
+            .resize(size, size, image::imageops::Lanczos3)
+            .save(out)
+            .map_err(|e| format!("{out}: {e}")),
+        Render::Convert => convert(src, size, out),
+    }
+}
+
+/// Caches the hicolor theme icon `icon` from the store path `path` as `iout` at 64px and 128px in
+/// `{output}/icons`. Larger icons are scaled down, and PNG icons up to `maxupscale` times smaller are scaled up. When
+/// the best source is rejected, the next best one is tried.
+pub fn findicons(
+    path: &str,
+    icon: &str,
+    iout: &str,
+    maxupscale: f32,
+    output: &str,
+    pkg: &str,
+    report: &mut PkgReport,
+) -> Vec<Element> {
+    let mut icondata = vec![];
+    let themepath =
+        |s: &str, ext: &str| format!("{path}/share/icons/hicolor/{s}/apps/{icon}.{ext}");
+    // Existing sources for an icon of `size` with their size, the best first
+    let candidates = |size: u32| -> Vec<(String, Option<u32>, Render)> {
+        let sizes = [64, 72, 96, 128, 192, 256, 512, 1024]
+            .into_iter()
+            .filter(|x| *x >= size)
+            .collect::<Vec<u32>>();
+        // Smaller icons as a last resort, the largest first
+        let smaller = [96, 72, 64, 48, 32, 24, 22, 16]
+            .into_iter()
+            .filter(|x| *x < size && size as f32 <= *x as f32 * maxupscale);
+
+        let mut all = vec![
+            (
+                themepath(&format!("{size}x{size}"), "png"),
+                Some(size),
+                Render::Copy,
+            ),
+            (
+                themepath(&format!("{size}x{size}"), "svg"),
+                None,
+                Render::Convert,
+            ),
+            (themepath("scalable", "svg"), None, Render::Convert),
+        ];
+        for s in sizes.iter().copied().chain(smaller) {
+            all.push((
+                themepath(&format!("{s}x{s}"), "png"),
+                Some(s),
+                Render::Resize,
+            ));
+        }
+        for s in &sizes {
+            all.push((themepath(&format!("{s}x{s}"), "svg"), None, Render::Convert));
+        }
+
+        let mut found: Vec<(String, Option<u32>, Render)> = vec![];
+        for c in all {
+            if Path::new(&c.0).exists() && !found.iter().any(|f| f.0 == c.0) {
+                found.push(c);
+            }
+        }
+        found
+    };
+    for size in [128, 64] {
+        let outpath = format!("{output}/icons/{size}x{size}/{iout}");
+        for (source, sourcesize, how) in candidates(size) {
+            if let Err(e) = checkaspect(&source) {
+                report.warn(pkg, format!("rejected icon: {e}"));
+                continue;
+            }
+            if let Err(e) = render(&source, how, size, &outpath) {
+                report.error(pkg, e);
+                continue;
+            }
+            if let Some(i) = cached(size, iout, &source, sourcesize, output, pkg, report) {
+                icondata.push(i);
+                break;
+            }
+        }
+    }
+    icondata
+}
@@ -0,0 +1,125 @@
+use crate::find::textelement;
+use std::{collections::BTreeMap, fs, path::Path};
+use xmltree::{Element, XMLNode};
+
+fn u32at(d: &[u8], o: usize, be: bool) -> Option<u32> {
+    let b: [u8; 4] = d.get(o..o + 4)?.try_into().ok()?;
+    Some(if be {
+        u32::from_be_bytes(b)
+    } else {
+        u32::from_le_bytes(b)
+    })
+}
+
+/// Number of translated messages in a gettext catalog, not counting the header
+pub fn mocount(file: &Path) -> Option<u32> {
+    let d = fs::read(file).ok()?;
+    let be = match d.get(0..4)? {
+        [0xde, 0x12, 0x04, 0x95] => false,
+        [0x95, 0x04, 0x12, 0xde] => true,
+        _ => return None,
+    };
+    let n = u32at(&d, 8, be)? as usize;
+    let origs = u32at(&d, 12, be)? as usize;
+    let trans = u32at(&d, 16, be)? as usize;
+    let mut count = 0;
+    for i in 0..n {
+        let olen = u32at(&d, origs + i * 8, be)?;
+        let tlen = u32at(&d, trans + i * 8, be)?;
+        // The header is the translation of the empty message
+        if olen > 0 && tlen > 0 {
+            count += 1;
+        }
+    }
+    Some(count)
+}
+
+/// Translated message counts of `{path}/share/locale/{lang}/LC_MESSAGES/{domain}.mo`, by domain and
+/// language
+pub fn catalogs(path: &str) -> BTreeMap<String, BTreeMap<String, u32>> {
+    let mut domains: BTreeMap<String, BTreeMap<String, u32>> = BTreeMap::new();
+    let langs = match fs::read_dir(format!("{path}/share/locale")) {
+        Ok(l) => l,
+        Err(_) => return domains,
+    };
+    for lang in langs.flatten() {
+        let name = lang.file_name().to_string_lossy().to_string();
+        let files = match fs::read_dir(lang.path().join("LC_MESSAGES")) {
+            Ok(f) => f,
+            Err(_) => continue,
+        };
+        for f in files.flatten() {
+            let p = f.path();
+            if p.extension().and_then(|e| e.to_str()) != Some("mo") {
+                continue;
+            }
+            let domain = match p.file_stem() {
+                Some(d) => d.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if let Some(c) = mocount(&p) {
+                domains.entry(domain).or_default().insert(name.clone(), c);
+            }
+        }
+    }
+    domains
+}
+
+/// Translation percentage of every language, relative to the most complete language of each domain
+/// as catalogs do not record untranslated messages. Only `domains` are used when not empty.
+pub fn percentages(path: &str, domains: &[String]) -> BTreeMap<String, u32> {
+    let mut translated: BTreeMap<String, u32> = BTreeMap::new();
+    let mut total = 0;
+    for (domain, langs) in catalogs(path) {
+        if !domains.is_empty() && !domains.contains(&domain) {
+            continue;
+        }
+        total += langs.values().max().copied().unwrap_or(0);
+        for (l, c) in langs {
+            *translated.entry(l).or_default() += c;
+        }
+    }
+    translated
+        .into_iter()
+        .filter(|(_, c)| *c > 0 && total > 0)
+        .map(|(l, c)| (l, (c as u64 * 100 / total as u64) as u32))
+        .filter(|(_, p)| *p > 0)
+        .collect()
+}
+
+/// Adds `<languages>` computed from the gettext catalogs of the store path, restricted to the
+/// domains of `<translation type="gettext">` if the component declares any. Components that already
+/// list their languages and fonts, whose languages mean something else, are left alone. Returns the
+/// added languages with their percentages.
+pub fn addlanguages(path: &str, x: &mut Element) -> BTreeMap<String, u32> {
+    if x.get_child("languages").is_some()
+        || x.attributes.get("type").map(|t| t.as_str()) == Some("font")
+    {
+        return BTreeMap::new();
+    }
+    let domains = x
+        .children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .filter(|c| {
+            c.name == "translation"
+                && c.attributes.get("type").map(|t| t.as_str()) == Some("gettext")
+        })
+        .filter_map(|c| c.get_text())
+        .map(|t| t.trim().to_string())
+        .collect::<Vec<_>>();
+
+    let langs = percentages(path, &domains);
+    if langs.is_empty() {
+        return langs;
+    }
+    let mut l = Element::new("languages");
+    for (lang, p) in &langs {
+        let mut e = textelement("lang", lang);
+        e.attributes
+            .insert("percentage".to_string(), p.to_string());
+        l.children.push(XMLNode::Element(e));
+    }
+    x.children.push(XMLNode::Element(l));
+    langs
+}
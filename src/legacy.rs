@@ -0,0 +1,113 @@
+use crate::find::textelement;
+use xmltree::{Element, XMLNode};
+
+/// Deprecated element names and their replacements
+const RENAMED: [(&str, &str); 2] = [
+    ("licence", "metadata_license"),
+    ("updatecontact", "update_contact"),
+];
+
+/// Legacy component types and their replacements
+fn modernkind(kind: &str) -> &str {
+    match kind {
+        "desktop" => "desktop-application",
+        "inputmethod" => "input-method",
+        k => k,
+    }
+}
+
+/// Strips the `_` marking untranslated strings for intltool from the names of `e` and its descendants
+fn striptranslatable(e: &mut Element) {
+    if let Some(n) = e.name.strip_prefix('_') {
+        e.name = n.to_string();
+    }
+    for c in e.children.iter_mut() {
+        if let XMLNode::Element(c) = c {
+            striptranslatable(c);
+        }
+    }
+}
+
+/// Turns a `<screenshot>` containing only an image URL into one with an `<image>` child, moving
+/// the size attributes to the image
+fn fixscreenshots(x: &mut Element) {
+    let screenshots = match x.get_mut_child("screenshots") {
+        Some(s) => s,
+        None => return,
+    };
+    for s in screenshots
+        .children
+        .iter_mut()
+        .filter_map(|c| c.as_mut_element())
+    {
+        if s.name != "screenshot" || s.children.iter().any(|c| c.as_element().is_some()) {
+            continue;
+        }
+        let url = match s.get_text() {
+            Some(u) if !u.trim().is_empty() => u.trim().to_string(),
+            _ => continue,
+        };
+        let mut image = textelement("image", &url);
+        image
+            .attributes
+            .insert("type".to_string(), "source".to_string());
+        for a in ["width", "height"] {
+            if let Some(v) = s.attributes.remove(a) {
+                image.attributes.insert(a.to_string(), v);
+            }
+        }
+        s.children = vec![XMLNode::Element(image)];
+    }
+}
+
+/// Converts legacy appdata to the current AppStream format: `<application>` becomes a desktop
+/// application component, legacy ids are normalized with a `<launchable>` for a `.desktop` id,
+/// deprecated elements are renamed and intltool markers are stripped.
+pub fn modernize(x: &mut Element) {
+    striptranslatable(x);
+
+    let idtype = x
+        .get_mut_child("id")
+        .and_then(|i| i.attributes.remove("type"));
+    if x.name == "application" {
+        x.name = "component".to_string();
+        x.attributes
+            .insert("type".to_string(), "desktop-application".to_string());
+    } else if let Some(t) = idtype {
+        x.attributes.entry("type".to_string()).or_insert(t);
+    }
+    if let Some(t) = x.attributes.get_mut("type") {
+        *t = modernkind(t).to_string();
+    }
+
+    let legacyid = x
+        .get_child("id")
+        .and_then(|i| i.get_text())
+        .map(|t| t.trim().to_string())
+        .filter(|t| t.ends_with(".desktop"));
+    if let Some(desktopid) = legacyid {
+        if let Some(i) = x.get_mut_child("id") {
+            i.children = vec![XMLNode::Text(
+                desktopid.trim_end_matches(".desktop").to_string(),
+            )];
+        }
+        let launchable = x.children.iter().filter_map(|c| c.as_element()).any(|c| {
+            c.name == "launchable"
+                && c.attributes.get("type").map(|t| t.as_str()) == Some("desktop-id")
+        });
+        if !launchable {
+            let mut l = textelement("launchable", &desktopid);
+            l.attributes
+                .insert("type".to_string(), "desktop-id".to_string());
+            x.children.push(XMLNode::Element(l));
+        }
+    }
+
+    for c in x.children.iter_mut().filter_map(|c| c.as_mut_element()) {
+        if let Some((_, new)) = RENAMED.iter().find(|(old, _)| c.name == *old) {
+            c.name = new.to_string();
+        }
+    }
+
+    fixscreenshots(x);
+}
@@ -1 +1,17 @@
-pub mod find;
\ No newline at end of file
+pub mod description;
+pub mod desktop;
+pub mod diff;
+pub mod download;
+pub mod find;
+pub mod gc;
+pub mod icon;
+pub mod languages;
+pub mod legacy;
+pub mod matcher;
+pub mod merge;
+pub mod media;
+pub mod nondesktop;
+pub mod provides;
+pub mod report;
+pub mod reproducible;
+pub mod sanitize;
@@ -1,17 +1,30 @@
-use nixos_appstream_generator::find::{dlmeta, findmeta, PkgData};
+use nixos_appstream_generator::{
+    diff,
+    find::{dlmeta, findmeta, GenOptions, PkgData},
+    gc::{self, humansize, GcPolicy, GcRoot, Threshold},
+    icon::{IconNames, IconPolicy, Policy},
+    merge,
+    report::{PkgReport, Report, Status},
+    reproducible,
+};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     fmt::Debug,
     fs::{self, File},
     io::{BufRead, BufReader},
+    panic::{self, AssertUnwindSafe},
     path::Path,
     process::{exit, Command},
+    sync::{Arc, Mutex},
+    time::Instant,
 };
 use sysinfo::{self, DiskExt, System, SystemExt};
-use clap::{self, ArgGroup, Parser};
+use clap::{self, ArgGroup, Parser, Subcommand};
 use owo_colors::{OwoColorize, Stream::Stdout};
 
+const REPORT: &str = "output/report.json";
+
 /// Generate Appstream data for a given package
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -36,11 +49,82 @@ struct Args {
     /// Weather to clean nix-store periodically
     #[clap(short, long)]
     clean: bool,
+
+    /// Collect garbage when free space drops below this size or percentage
+    #[clap(long)]
+    gc_min_free: Option<Threshold>,
+
+    /// Collect garbage when used space on the watched filesystem grows beyond this size or percentage
+    #[clap(long)]
+    gc_max_used: Option<Threshold>,
+
+    /// Collect garbage once the run used this size or percentage of the space free when it started.
+    /// Defaults to 50G unless --gc-min-free or --gc-max-used is given
+    #[clap(long)]
+    gc_max_growth: Option<Threshold>,
+
+    /// Space to free on top of what the thresholds require
+    #[clap(long, default_value = "10G")]
+    gc_headroom: Threshold,
+
+    /// Path whose filesystem is watched for garbage collection
+    #[clap(long, default_value = "/nix/store")]
+    gc_watch: String,
+
+    /// Mirror screenshots into output/media and rewrite them to this base URL
+    #[clap(short, long)]
+    media_url: Option<String>,
+
+    /// Name cached icon files after the icon, or prefix them with the component id or package so packages using
+    /// the same icon name do not overwrite each other's icons: icon, component or package
+    #[clap(long, default_value = "icon")]
+    icon_names: IconNames,
+
+    /// Icon types of desktop applications as TYPE=POLICY pairs separated by commas. Types are stock, cached, remote
+    /// (published below --media-url) and local (store paths), policies are drop, keep (upstream icons) and generate
+    #[clap(long, default_value = "cached=generate")]
+    icon_policy: IconPolicy,
+
+    /// Scale PNG icons up to this many times their size when no larger icon exists, 1 to never scale up
+    #[clap(long, default_value = "2")]
+    max_upscale: f32,
+
+    /// Generate every package twice and report output files that differ between the runs
+    #[clap(long)]
+    check_reproducible: bool,
+
+    #[clap(subcommand)]
+    command: Option<Commands>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct CustomPackages {
-    packages: HashMap<String, CustomPackage>,
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Compare two output directories or merged catalogs
+    Diff {
+        /// Old output directory or catalog
+        old: String,
+
+        /// New output directory or catalog
+        new: String,
+
+        /// Also write the differences as JSON to this file
+        #[clap(long)]
+        json: Option<String>,
+    },
+    /// Combine the output directories of sharded runs into one with a single catalog and report
+    Merge {
+        /// Output directories to merge, files of earlier ones win on conflicts
+        #[clap(required = true, min_values = 2)]
+        inputs: Vec<String>,
+
+        /// Directory to write the merged output to, which must not exist or be empty
+        #[clap(short, long, default_value = "output")]
+        output: String,
+
+        /// Origin of the catalog
+        #[clap(long, default_value = "nixos")]
+        origin: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -49,6 +133,8 @@ struct CustomPackage {
     icon: Option<String>,
     id: Option<String>,
     output: Option<CustomPackageOutput>,
+    /// Desktop file to pair with each metainfo file, keyed by metainfo file name or component id
+    pairs: Option<HashMap<String, String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -57,11 +143,56 @@ struct CustomPackageOutput {
     icon: Option<String>,
 }
 
+/// Removes `tmp/`, including the GC roots of in-flight packages, when dropped, so roots are released even if
+/// the run panics
+struct TmpGuard;
+
+impl Drop for TmpGuard {
+    fn drop(&mut self) {
+        cleantmp();
+    }
+}
+
+fn cleantmp() {
+    if Path::new("tmp").exists() {
+        if let Err(e) = fs::remove_dir_all("tmp") {
+            println!("{} Could not remove tmp: {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+        }
+    }
+}
+
 fn main() {
     let args = Args::parse();
+    if let Some(Commands::Diff { old, new, json }) = &args.command {
+        rundiff(old, new, json.as_deref());
+        return;
+    }
+    if let Some(Commands::Merge { inputs, output, origin }) = &args.command {
+        runmerge(inputs, output, origin);
+        return;
+    }
     let mut sys = System::new_all();
-    let disk = sys.disks_mut().iter_mut().find(|x| x.mount_point() == Path::new("/")).unwrap();
-    let cleanspace = &disk.available_space() - 53687091200; // 50GB
+    let disk = match gc::finddisk(sys.disks_mut(), Path::new(&args.gc_watch)) {
+        Some(d) => d,
+        None => {
+            println!("Could not find filesystem for {}", args.gc_watch);
+            exit(1);
+        }
+    };
+    let gcpolicy = if args.gc_min_free.is_none() && args.gc_max_used.is_none() && args.gc_max_growth.is_none() {
+        GcPolicy {
+            headroom: args.gc_headroom,
+            ..Default::default()
+        }
+    } else {
+        GcPolicy {
+            minfree: args.gc_min_free,
+            maxused: args.gc_max_used,
+            maxgrowth: args.gc_max_growth,
+            headroom: args.gc_headroom,
+        }
+    };
+    let startfree = disk.available_space();
 
     let data: HashMap<String, CustomPackage> = if let Some(custompath) = args.data {
         let inputdata = fs::read_to_string(custompath).expect("Failed to read json file");
@@ -73,123 +204,241 @@ fn main() {
         HashMap::new()
     };
 
+    if args.max_upscale < 1.0 {
+        println!("{} --max-upscale must be at least 1", "error:".if_supports_color(Stdout, |x| x.red()));
+        exit(1);
+    }
+    if args.icon_policy.remote == Policy::Generate && args.media_url.is_none() {
+        println!("{} generating remote icons needs --media-url", "error:".if_supports_color(Stdout, |x| x.red()));
+        exit(1);
+    }
+
+    let opts = GenOptions {
+        mediaurl: args.media_url,
+        iconnames: args.icon_names,
+        iconpolicy: args.icon_policy,
+        maxupscale: args.max_upscale,
+    };
+
     if !Path::new("tmp").exists() {
         fs::create_dir("tmp").unwrap();
     }
+    let _tmpguard = TmpGuard;
+    let report = Arc::new(Mutex::new(Report::default()));
+    let interrupted = Arc::clone(&report);
+    if let Err(e) = ctrlc::set_handler(move || {
+        println!("{}", "Interrupted, cleaning up...".if_supports_color(Stdout, |x| x.yellow()));
+        // Keep the results of the packages that already finished
+        if let Ok(report) = interrupted.lock() {
+            writereport(&report);
+        }
+        cleantmp();
+        exit(130);
+    }) {
+        println!("{} Could not set Ctrl-C handler: {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+    }
 
-    if let Some(pkg) = args.package {
-        if let Some(pkgdat) = data.get(&pkg) {
-            let pkgdata = PkgData {
-                id: pkgdat.id.clone(),
-                icon: pkgdat.icon.clone(),
-                outputicon: if let Some(out) = &pkgdat.output {
-                    out.icon.clone()
-                } else {
-                    None
-                },
-                outputmetainfo: if let Some(out) = &pkgdat.output {
-                    out.metainfo.clone()
-                } else {
-                    None
-                },
-            };
-            gendata(&pkg, false, pkgdat.metainfo.clone(), pkgdata);
+    let run = |pkg: &str| {
+        if args.check_reproducible {
+            checkreproducible(pkg, &data, &opts)
         } else {
-            gendata(&pkg, false, None, PkgData::default());
+            rundata(pkg, &data, &opts)
         }
-    } else if let Some(listfile) = args.list {
-
+    };
 
+    if let Some(pkg) = args.package {
+        let r = run(&pkg);
+        report.lock().unwrap().add(&pkg, r);
+    } else if let Some(listfile) = args.list {
         if let Ok(file) = File::open(&listfile) {
             let reader = BufReader::new(file);
-            for pkg in reader.lines().flatten() {
-                disk.refresh();
-                let clean = disk.available_space() < cleanspace;
-                eprintln!("AVAILABLE SPACE: {}", disk.available_space());
-                eprintln!("CLEAN AFTER: {}", cleanspace);
-                if let Some(pkgdat) = data.get(&pkg) {
-                    let pkgdata = PkgData {
-                        id: pkgdat.id.clone(),
-                        icon: pkgdat.icon.clone(),
-                        outputicon: if let Some(out) = &pkgdat.output {
-                            out.icon.clone()
-                        } else {
-                            None
-                        },
-                        outputmetainfo: if let Some(out) = &pkgdat.output {
-                            out.metainfo.clone()
-                        } else {
-                            None
-                        },
-                    };
-                    gendata(&pkg, clean && args.clean, pkgdat.metainfo.clone(), pkgdata);
-                } else {
-                    gendata(&pkg, clean && args.clean, None, PkgData::default());
+            for pkg in reader.lines().map_while(Result::ok) {
+                let r = run(&pkg);
+                report.lock().unwrap().add(&pkg, r);
+
+                if args.clean {
+                    disk.refresh();
+                    eprintln!("AVAILABLE SPACE: {}", humansize(disk.available_space()));
+                    if let Some(needed) = gcpolicy.needed(disk, startfree) {
+                        gc::collect(Some(needed));
+                    }
                 }
             }
         } else {
             println!("Could not open file {}", listfile);
+            cleantmp();
             exit(1);
         }
     } else {
         println!("No package or package list specified");
+        cleantmp();
         std::process::exit(1);
     }
 
     if args.clean {
-        println!("{}", "Cleaning nix store...".if_supports_color(Stdout, |x| x.purple()));
-        match Command::new("nix-store").arg("--gc").output() {
-            Ok(_) => (),
-            Err(_) => println!("{}", "Could not run nix-store --gc".if_supports_color(Stdout, |x| x.red())),
+        gc::collect(None);
+    }
+
+    let report = report.lock().unwrap();
+    writereport(&report);
+    report.summary();
+}
+
+fn writereport(report: &Report) {
+    if let Err(e) = report.write(REPORT) {
+        println!("{} {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+    }
+}
+
+fn rundiff(old: &str, new: &str, json: Option<&str>) {
+    let load = |p: &str| match diff::load(p) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{} {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+            exit(1);
+        }
+    };
+    let d = diff::diff(&load(old), &load(new));
+    d.print();
+    if let Some(path) = json {
+        if let Err(e) = d.write(path) {
+            println!("{} {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+            exit(1);
         }
     }
+}
 
-    if Path::new("tmp").exists() {
-        fs::remove_dir_all("tmp").unwrap();
+fn runmerge(inputs: &[String], output: &str, origin: &str) {
+    let fail = |e: String| -> ! {
+        println!("{} {}", "error:".if_supports_color(Stdout, |x| x.red()), e);
+        exit(1);
+    };
+    let m = merge::merge(inputs, output).unwrap_or_else(|e| fail(e));
+    let n = merge::catalog(output, origin).unwrap_or_else(|e| fail(e));
+    for c in &m.conflicts {
+        println!("{} {c}", "conflict:".if_supports_color(Stdout, |x| x.red()));
+    }
+    println!(
+        "Merged {} directories into {output}: {} files, {n} components in catalog.xml, {} conflicts",
+        inputs.len(),
+        m.files,
+        m.conflicts.len()
+    );
+    m.report.summary();
+    if !m.conflicts.is_empty() {
+        exit(1);
+    }
+}
+
+/// Runs a package twice and records every output file that is not identical between the runs as an
+/// error. Files written by the first run are removed before the second one, so both runs start from
+/// the same state. The report records the time of both runs, as that is what the package costs a
+/// sharded run.
+fn checkreproducible(pkg: &str, data: &HashMap<String, CustomPackage>, opts: &GenOptions) -> PkgReport {
+    let start = Instant::now();
+    let before = reproducible::snapshot("output");
+    let mut r = rundata(pkg, data, opts);
+    let first = reproducible::written("output", &before, REPORT);
+    for f in first.keys() {
+        let _ = fs::remove_file(f);
+    }
+
+    let before = reproducible::snapshot("output");
+    rundata(pkg, data, opts);
+    let second = reproducible::written("output", &before, REPORT);
+
+    let diffs = reproducible::compare(&first, &second);
+    if diffs.is_empty() {
+        println!(
+            "{pkg}: {}",
+            format!("reproducible ({} files)", first.len()).if_supports_color(Stdout, |x| x.green())
+        );
+    }
+    for d in diffs {
+        r.error(pkg, format!("not reproducible: {d}"));
     }
+    r.seconds = start.elapsed().as_secs_f64();
+    r
 }
 
-fn gendata(pkg: &str, clean: bool, metaoverride: Option<String>, pkgdata: PkgData) {
+/// Processes a single package, isolating any panic so it only fails this package, and records how
+/// long it took
+fn rundata(pkg: &str, data: &HashMap<String, CustomPackage>, opts: &GenOptions) -> PkgReport {
+    let start = Instant::now();
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        if let Some(pkgdat) = data.get(pkg) {
+            let pkgdata = PkgData {
+                id: pkgdat.id.clone(),
+                icon: pkgdat.icon.clone(),
+                outputicon: if let Some(out) = &pkgdat.output {
+                    out.icon.clone()
+                } else {
+                    None
+                },
+                outputmetainfo: if let Some(out) = &pkgdat.output {
+                    out.metainfo.clone()
+                } else {
+                    None
+                },
+                pairs: pkgdat.pairs.clone().unwrap_or_default(),
+            };
+            gendata(pkg, pkgdat.metainfo.clone(), pkgdata, opts)
+        } else {
+            gendata(pkg, None, PkgData::default(), opts)
+        }
+    }));
+    let mut r = match res {
+        Ok(r) => r,
+        Err(e) => {
+            let r = PkgReport::panicked(e);
+            println!("{pkg}: {} {}", "error:".if_supports_color(Stdout, |x| x.red()), r.errors[0]);
+            r
+        }
+    };
+    r.seconds = start.elapsed().as_secs_f64();
+    r
+}
+
+fn gendata(pkg: &str, metaoverride: Option<String>, pkgdata: PkgData, opts: &GenOptions) -> PkgReport {
+    let mut report = PkgReport::default();
+
+    // Keep the outputs alive until this package is done, a collection may run in between
+    let root = GcRoot::new(pkg);
     let out = Command::new("nix-build")
-        .arg("--no-out-link")
+        .arg("-o")
+        .arg(root.link())
         .arg("<nixpkgs>")
         .arg("-A")
         .arg(pkg)
         .output();
 
-    if let Ok(o) = out {
-        if o.status.success() {
-            if !Path::new("output/icons/128x128").exists() {
-                fs::create_dir_all("output/icons/128x128").unwrap();
-            }
-            if !Path::new("output/icons/64x64").exists() {
-                fs::create_dir_all("output/icons/64x64").unwrap();
-            }
-            if !Path::new("output/metadata").exists() {
-                fs::create_dir_all("output/metadata").unwrap();
+    match out {
+        Ok(o) if o.status.success() => {
+            for dir in ["output/icons/128x128", "output/icons/64x64", "output/metadata"] {
+                if let Err(e) = fs::create_dir_all(dir) {
+                    report.error(pkg, format!("{dir}: {e}"));
+                    return report;
+                }
             }
 
-            let path = std::str::from_utf8(&o.stdout).unwrap().replace('\n', "");
+            let path = String::from_utf8_lossy(&o.stdout).replace('\n', "");
 
             if let Some(metaurl) = metaoverride {
-                dlmeta(path, metaurl, pkg, pkgdata);
+                dlmeta(path, metaurl, pkg, pkgdata, opts, &mut report);
             } else if Path::exists(Path::new(&format!("{}/share/metainfo", path))) {
-                findmeta(path, "metainfo".to_string(), pkg, pkgdata);
+                findmeta(path, "metainfo".to_string(), pkg, pkgdata, opts, &mut report);
             } else if Path::exists(Path::new(&format!("{}/share/appdata", path))) {
-                findmeta(path, "appdata".to_string(), pkg, pkgdata);
+                findmeta(path, "appdata".to_string(), pkg, pkgdata, opts, &mut report);
             } else {
                 println!("{pkg}: {}", "No metadata found".if_supports_color(Stdout, |x| x.red()));
+                report.status = Status::NoMetadata;
             }
-        } else {
-            println!("{} failed to build {}", "error:".if_supports_color(Stdout, |x| x.red()), pkg);
         }
-    }
-
-    if clean {
-        println!("{}", "Cleaning nix store...".if_supports_color(Stdout, |x| x.purple()));
-        match Command::new("nix-store").arg("--gc").output() {
-            Ok(_) => (),
-            Err(_) => println!("{}", "Could not run nix-store --gc".if_supports_color(Stdout, |x| x.red())),
+        Ok(_) => {
+            println!("{} failed to build {}", "error:".if_supports_color(Stdout, |x| x.red()), pkg);
+            report.status = Status::BuildFailed;
         }
+        Err(e) => report.error(pkg, format!("nix-build: {e}")),
     }
+    report
 }
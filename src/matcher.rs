@@ -0,0 +1,316 @@
+use crate::{desktop::DesktopEntry, find::readcomponent, sanitize::Changes};
+use std::collections::HashMap;
+use xmltree::Element;
+
+/// Minimum score a desktop file needs to be paired with a metainfo file
+pub const MINSCORE: i32 = 10;
+
+/// A desktop file considered for a metainfo file, with the signals that matched
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub desktop: String,
+    pub score: i32,
+    pub reasons: Vec<String>,
+}
+
+/// Outcome of resolving the desktop file of one metainfo file
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub meta: String,
+    /// The chosen desktop file, `None` if no candidate was accepted
+    pub desktop: Option<DesktopEntry>,
+    /// Whether the metainfo file explicitly describes a desktop application. Unpaired components
+    /// without a type are handled as non-desktop components instead.
+    pub isdesktop: bool,
+    pub explanation: String,
+}
+
+fn filename(p: &str) -> &str {
+    p.split('/').next_back().unwrap_or_default()
+}
+
+fn text(e: Option<&Element>) -> Option<String> {
+    e.and_then(|e| e.get_text())
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+/// Binary name of an `Exec=` command line, skipping `env` and variable assignments
+pub(crate) fn execbinary(exec: &str) -> Option<String> {
+    exec.split_whitespace()
+        .map(|a| a.trim_matches('"'))
+        .find(|a| *a != "env" && !a.contains('='))
+        .map(|a| filename(a).to_string())
+}
+
+struct MetaInfo {
+    file: String,
+    /// Component id without a legacy `.desktop` suffix
+    id: String,
+    /// Raw `<id>` text, which may be a legacy desktop file name
+    rawid: Option<String>,
+    launchables: Vec<String>,
+    binaries: Vec<String>,
+    isdesktop: bool,
+    /// Desktop applications and components without a type are paired
+    candidate: bool,
+}
+
+fn readmeta(m: &str) -> MetaInfo {
+    let fileid = filename(m)
+        .replace(".appdata", "")
+        .replace(".metainfo", "")
+        .replace(".xml", "");
+    // Read like the component is generated, so files that need their entities fixed are paired too
+    let x = match readcomponent(m, &mut Changes::default()).ok() {
+        Some(x) => x,
+        None => {
+            return MetaInfo {
+                file: m.to_string(),
+                id: fileid,
+                rawid: None,
+                launchables: vec![],
+                binaries: vec![],
+                isdesktop: false,
+                candidate: false,
+            }
+        }
+    };
+
+    let rawid = text(x.get_child("id"));
+    let idtype = x.get_child("id").and_then(|i| i.attributes.get("type"));
+    let kind = x.attributes.get("type");
+    let isdesktop = x.name == "application"
+        || matches!(
+            kind.map(|t| t.as_str()),
+            Some("desktop" | "desktop-application")
+        )
+        || matches!(
+            idtype.map(|t| t.as_str()),
+            Some("desktop" | "desktop-application")
+        );
+    let launchables = x
+        .children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .filter(|c| {
+            c.name == "launchable"
+                && c.attributes.get("type").map(|t| t.as_str()) == Some("desktop-id")
+        })
+        .filter_map(|c| text(Some(c)))
+        .collect();
+    let binaries = x
+        .get_child("provides")
+        .map(|p| {
+            p.children
+                .iter()
+                .filter_map(|c| c.as_element())
+                .filter(|c| c.name == "binary")
+                .filter_map(|c| text(Some(c)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    MetaInfo {
+        file: m.to_string(),
+        id: rawid
+            .as_ref()
+            .map(|i| i.trim_end_matches(".desktop").to_string())
+            .unwrap_or(fileid),
+        rawid,
+        launchables,
+        binaries,
+        isdesktop,
+        candidate: isdesktop || kind.is_none(),
+    }
+}
+
+fn score(meta: &MetaInfo, desktop: &DesktopEntry, pkg: &str, sole: bool) -> Candidate {
+    let name = desktop.filename();
+    let stem = name.trim_end_matches(".desktop");
+    let lastseg = meta
+        .id
+        .split('.')
+        .next_back()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let mut c = Candidate {
+        desktop: desktop.file.clone(),
+        score: 0,
+        reasons: vec![],
+    };
+    let mut add = |points: i32, reason: String| {
+        c.score += points;
+        c.reasons.push(format!("{reason} ({points:+})"));
+    };
+
+    if meta.launchables.iter().any(|l| l == name) {
+        add(100, "launchable desktop-id".to_string());
+    }
+    if meta.rawid.as_deref() == Some(name) {
+        add(90, "legacy desktop id".to_string());
+    }
+    if stem == meta.id {
+        add(80, "file name matches id".to_string());
+    }
+    if desktop.get("X-Flatpak").as_ref() == Some(&meta.id) {
+        add(70, "X-Flatpak matches id".to_string());
+    }
+    if sole {
+        add(50, "only metainfo and desktop file".to_string());
+    }
+    let exec = desktop.get("Exec").and_then(|e| execbinary(&e));
+    if let Some(e) = &exec {
+        if meta.binaries.contains(e) {
+            add(40, format!("Exec binary {e} is provided"));
+        } else if e.to_lowercase() == lastseg || e == pkg {
+            add(25, format!("Exec binary {e} matches"));
+        }
+    }
+    if let Some(w) = desktop.get("StartupWMClass") {
+        if w.eq_ignore_ascii_case(&meta.id) || w.to_lowercase() == lastseg {
+            add(30, "StartupWMClass matches id".to_string());
+        }
+    }
+    if stem == pkg {
+        add(20, "file name matches package".to_string());
+    }
+    if stem != meta.id && stem.to_lowercase().contains(&meta.id.to_lowercase()) {
+        add(15, "file name contains id".to_string());
+    } else if stem != meta.id && !lastseg.is_empty() && stem.to_lowercase().contains(&lastseg) {
+        add(10, "file name contains id suffix".to_string());
+    }
+    if stem.contains('.') {
+        add(5, "reverse-DNS file name".to_string());
+    }
+    // Hidden entries are usually helpers, but may still be the one the metainfo file describes
+    if let Some(why) = desktop.hidden() {
+        add(-30, format!("hidden by {why}"));
+    }
+    c
+}
+
+/// Pins that can not apply because the package has no metainfo file or component they name, or no
+/// desktop file they point at
+pub fn unmatchedpins(
+    meta: &[String],
+    desktops: &[DesktopEntry],
+    pins: &HashMap<String, String>,
+) -> Vec<String> {
+    let metas = meta.iter().map(|m| readmeta(m)).collect::<Vec<_>>();
+    let mut pins = pins.iter().collect::<Vec<_>>();
+    pins.sort();
+    let mut msgs = vec![];
+    for (k, d) in pins {
+        if !metas.iter().any(|m| filename(&m.file) == k || m.id == *k) {
+            msgs.push(format!("pin {k} -> {d}: no metainfo file or component {k}"));
+        } else if !desktops.iter().any(|e| e.filename() == d) {
+            msgs.push(format!("pin {k} -> {d}: no desktop file {d}"));
+        }
+    }
+    msgs
+}
+
+/// Pairs metainfo files with desktop files. Every candidate pair is scored by the signals it
+/// matches, then pairs are assigned greedily from the highest score so no desktop file is used twice.
+/// `pins` maps a metainfo file name or component id to a desktop file name and always wins.
+pub fn resolve(
+    meta: &[String],
+    desktops: &[DesktopEntry],
+    pkg: &str,
+    pins: &HashMap<String, String>,
+) -> Vec<Resolved> {
+    let metas = meta.iter().map(|m| readmeta(m)).collect::<Vec<_>>();
+    let sole = meta.len() == 1 && desktops.len() == 1;
+
+    let mut candidates: Vec<(usize, Candidate)> = vec![];
+    for (i, m) in metas.iter().enumerate() {
+        let pin = pins.get(filename(&m.file)).or_else(|| pins.get(&m.id));
+        if !m.candidate && pin.is_none() {
+            continue;
+        }
+        for d in desktops {
+            let mut c = score(m, d, pkg, sole);
+            if pin.map(|p| p == d.filename()) == Some(true) {
+                c.score += 1000;
+                c.reasons
+                    .insert(0, "pinned in custom data (+1000)".to_string());
+            }
+            candidates.push((i, c));
+        }
+    }
+    candidates.sort_by(|(ai, a), (bi, b)| {
+        b.score
+            .cmp(&a.score)
+            .then(ai.cmp(bi))
+            .then(a.desktop.cmp(&b.desktop))
+    });
+
+    let mut chosen: Vec<Option<Candidate>> = vec![None; metas.len()];
+    let mut used: Vec<String> = vec![];
+    let mut ambiguous: Vec<Option<String>> = vec![None; metas.len()];
+    for (i, c) in &candidates {
+        if c.score < MINSCORE
+            || chosen[*i].is_some()
+            || ambiguous[*i].is_some()
+            || used.contains(&c.desktop)
+        {
+            continue;
+        }
+        // Refuse to guess between equally good candidates
+        let tie = candidates.iter().find(|(j, o)| {
+            j == i && o.desktop != c.desktop && o.score == c.score && !used.contains(&o.desktop)
+        });
+        if let Some((_, o)) = tie {
+            ambiguous[*i] = Some(format!(
+                "ambiguous between {} and {} (score {})",
+                filename(&c.desktop),
+                filename(&o.desktop),
+                c.score
+            ));
+            continue;
+        }
+        used.push(c.desktop.clone());
+        chosen[*i] = Some(c.clone());
+    }
+
+    metas
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let best = candidates.iter().find(|(j, _)| *j == i).map(|(_, c)| c);
+            let explanation = if let Some(c) = &chosen[i] {
+                format!(
+                    "paired with {} (score {}: {})",
+                    filename(&c.desktop),
+                    c.score,
+                    c.reasons.join(", ")
+                )
+            } else if !m.candidate {
+                "not a desktop application".to_string()
+            } else if let Some(a) = &ambiguous[i] {
+                format!("no desktop file chosen: {a}")
+            } else if let Some(b) = best {
+                format!(
+                    "no desktop file chosen: best candidate {} scored {} (minimum {MINSCORE}){}{}",
+                    filename(&b.desktop),
+                    b.score,
+                    if b.reasons.is_empty() { "" } else { ": " },
+                    b.reasons.join(", ")
+                )
+            } else {
+                "no desktop file chosen: no candidates".to_string()
+            };
+            Resolved {
+                meta: m.file.clone(),
+                desktop: chosen[i]
+                    .as_ref()
+                    .and_then(|c| desktops.iter().find(|d| d.file == c.desktop))
+                    .cloned(),
+                isdesktop: m.isdesktop,
+                explanation,
+            }
+        })
+        .collect()
+}
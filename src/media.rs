@@ -0,0 +1,147 @@
+use crate::{description::LANG, download::dlcached};
+use owo_colors::{OwoColorize, Stream::Stdout};
+use std::fs;
+use xmltree::{Element, XMLNode};
+
+/// Thumbnail sizes generated for every mirrored screenshot, matching appstream-generator
+pub const THUMBNAIL_SIZES: [(u32, u32); 4] = [(1248, 702), (752, 423), (624, 351), (224, 126)];
+
+fn imageelement(
+    kind: &str,
+    width: u32,
+    height: u32,
+    url: String,
+    lang: Option<&String>,
+) -> Element {
+    let mut e = Element::new("image");
+    e.attributes.insert("type".to_string(), kind.to_string());
+    e.attributes.insert("width".to_string(), width.to_string());
+    e.attributes
+        .insert("height".to_string(), height.to_string());
+    if let Some(l) = lang {
+        e.attributes.insert(LANG.to_string(), l.to_string());
+    }
+    e.children.push(XMLNode::Text(url));
+    e
+}
+
+/// Downloads a single source screenshot through the cache `cachedir` and writes the original and its
+/// thumbnails to `dir`. Returns the `<image>` elements pointing at `baseurl`.
+fn mirrorimage(
+    url: &str,
+    n: usize,
+    dir: &str,
+    cachedir: &str,
+    baseurl: &str,
+    lang: Option<&String>,
+) -> Result<Vec<Element>, String> {
+    let cached = dlcached(url, cachedir)?;
+    // Cached downloads have no extension, so the format has to be sniffed from the contents
+    let img = image::io::Reader::open(&cached)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| e.to_string())?
+        .decode()
+        .map_err(|e| format!("{}: {}", url, e))?;
+
+    let mut images = vec![];
+    let orig = format!("image-{n}_orig.png");
+    img.save(format!("{dir}/{orig}"))
+        .map_err(|e| e.to_string())?;
+    images.push(imageelement(
+        "source",
+        img.width(),
+        img.height(),
+        format!("{baseurl}/{orig}"),
+        lang,
+    ));
+
+    for (w, h) in THUMBNAIL_SIZES {
+        // Never upscale, the source image is always available for large displays
+        if img.width() <= w && img.height() <= h {
+            continue;
+        }
+        let thumb = img.resize(w, h, image::imageops::Lanczos3);
+        let name = format!("image-{n}_{w}x{h}.png");
+        thumb
+            .save(format!("{dir}/{name}"))
+            .map_err(|e| e.to_string())?;
+        images.push(imageelement(
+            "thumbnail",
+            thumb.width(),
+            thumb.height(),
+            format!("{baseurl}/{name}"),
+            lang,
+        ));
+    }
+    Ok(images)
+}
+
+/// Mirrors every screenshot of the component into `{output}/media/{id}/screenshots` and rewrites the
+/// `<image>` elements to point at `mediaurl`. Downloads are cached in `cachedir`. Screenshots that
+/// fail to download keep their upstream URLs.
+pub fn mirrorscreenshots(
+    x: &mut Element,
+    id: &str,
+    pkg: &str,
+    mediaurl: &str,
+    output: &str,
+    cachedir: &str,
+) {
+    let screenshots = match x.get_mut_child("screenshots") {
+        Some(s) => s,
+        None => return,
+    };
+
+    let dir = format!("{output}/media/{id}/screenshots");
+    let baseurl = format!("{}/{id}/screenshots", mediaurl.trim_end_matches('/'));
+    if let Err(e) = fs::create_dir_all(&dir) {
+        println!("{pkg}: {}", e.if_supports_color(Stdout, |x| x.red()));
+        return;
+    }
+
+    let mut n = 0;
+    for s in screenshots
+        .children
+        .iter_mut()
+        .filter_map(|x| x.as_mut_element())
+    {
+        if s.name != "screenshot" {
+            continue;
+        }
+
+        let mut mirrored: Vec<Element> = vec![];
+        let mut failed = false;
+        for i in s.children.iter().filter_map(|x| x.as_element()) {
+            if i.name != "image"
+                || i.attributes.get("type").map(|t| t.as_str()) == Some("thumbnail")
+            {
+                continue;
+            }
+            let url = match i.get_text() {
+                Some(u) => u.trim().to_string(),
+                None => continue,
+            };
+            n += 1;
+            match mirrorimage(&url, n, &dir, cachedir, &baseurl, i.attributes.get(LANG)) {
+                Ok(images) => mirrored.extend(images),
+                Err(e) => {
+                    println!(
+                        "{pkg}: {} {}",
+                        "failed to mirror screenshot".if_supports_color(Stdout, |x| x.yellow()),
+                        e
+                    );
+                    failed = true;
+                }
+            }
+        }
+
+        if failed || mirrored.is_empty() {
+            continue;
+        }
+
+        s.children
+            .retain(|x| !matches!(x.as_element(), Some(e) if e.name == "image"));
+        s.children
+            .extend(mirrored.into_iter().map(XMLNode::Element));
+    }
+}
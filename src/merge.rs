@@ -0,0 +1,134 @@
+use crate::{
+    description::fixlangattrs,
+    report::{PkgReport, Report},
+    reproducible::{sha256, snapshot},
+};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    path::Path,
+};
+use xmltree::{Element, XMLNode};
+
+/// Files of an output directory that are not copied but combined
+const COMBINED: [&str; 2] = ["report.json", "catalog.xml"];
+
+#[derive(Debug, Default)]
+pub struct Merged {
+    /// Number of files copied
+    pub files: usize,
+    /// Files and packages whose results differ between the inputs
+    pub conflicts: Vec<String>,
+    pub report: Report,
+}
+
+fn samereport(a: &PkgReport, b: &PkgReport) -> bool {
+    a.status == b.status && a.components == b.components
+}
+
+/// Copies the files of several output directories into `output`, which must not exist or be empty,
+/// and combines their reports. A file that exists in more than one input with different contents
+/// and a package with different results in more than one report are conflicts, for which the
+/// version of the first input is kept.
+pub fn merge(inputs: &[String], output: &str) -> Result<Merged, String> {
+    if fs::read_dir(output)
+        .map(|mut d| d.next().is_some())
+        .unwrap_or(false)
+    {
+        return Err(format!("{output}: directory is not empty"));
+    }
+
+    let mut m = Merged::default();
+    // Hash and input of every copied file, by path relative to the output directory
+    let mut files: BTreeMap<String, (String, &str)> = BTreeMap::new();
+    let mut packages: BTreeMap<String, &str> = BTreeMap::new();
+    for input in inputs {
+        if !Path::new(input).is_dir() {
+            return Err(format!("{input}: not a directory"));
+        }
+        for f in snapshot(input).into_keys() {
+            let rel = match Path::new(&f).strip_prefix(input) {
+                Ok(r) => r.to_string_lossy().to_string(),
+                Err(_) => continue,
+            };
+            if COMBINED.contains(&rel.as_str()) {
+                continue;
+            }
+            let data = fs::read(&f).map_err(|e| format!("{f}: {e}"))?;
+            let hash = sha256(&data);
+            match files.get(&rel) {
+                Some((h, _)) if *h == hash => (),
+                Some((_, first)) => m
+                    .conflicts
+                    .push(format!("{rel}: differs between {first} and {input}")),
+                None => {
+                    let dest = Path::new(output).join(&rel);
+                    if let Some(parent) = dest.parent() {
+                        fs::create_dir_all(parent)
+                            .map_err(|e| format!("{}: {e}", parent.display()))?;
+                    }
+                    fs::write(&dest, data).map_err(|e| format!("{}: {e}", dest.display()))?;
+                    files.insert(rel, (hash, input));
+                    m.files += 1;
+                }
+            }
+        }
+
+        let path = Path::new(input).join("report.json");
+        if !path.is_file() {
+            continue;
+        }
+        let data = fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let report: Report =
+            serde_json::from_str(&data).map_err(|e| format!("{}: {e}", path.display()))?;
+        for (pkg, r) in report.packages {
+            match m.report.packages.get(&pkg) {
+                Some(old) if samereport(old, &r) => (),
+                Some(_) => m.conflicts.push(format!(
+                    "{pkg}: results differ between {} and {input}",
+                    packages[&pkg]
+                )),
+                None => {
+                    packages.insert(pkg.clone(), input);
+                    m.report.packages.insert(pkg, r);
+                }
+            }
+        }
+    }
+
+    m.report
+        .write(&Path::new(output).join("report.json").to_string_lossy())?;
+    Ok(m)
+}
+
+/// Writes every component in `{dir}/metadata` to a single `{dir}/catalog.xml`, ordered by file name
+pub fn catalog(dir: &str, origin: &str) -> Result<usize, String> {
+    let mut files = fs::read_dir(Path::new(dir).join("metadata"))
+        .map(|d| d.flatten().map(|e| e.path()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    files.retain(|f| f.extension().and_then(|e| e.to_str()) == Some("xml"));
+    files.sort();
+
+    let mut components = Element::new("components");
+    components
+        .attributes
+        .insert("version".to_string(), "0.16".to_string());
+    components
+        .attributes
+        .insert("origin".to_string(), origin.to_string());
+    for f in &files {
+        let data = fs::read_to_string(f).map_err(|e| format!("{}: {e}", f.display()))?;
+        let mut x =
+            Element::parse(data.as_bytes()).map_err(|e| format!("{}: {e:?}", f.display()))?;
+        fixlangattrs(&mut x);
+        components.children.push(XMLNode::Element(x));
+    }
+
+    let path = Path::new(dir).join("catalog.xml");
+    let writer = xmltree::EmitterConfig::new().perform_indent(true);
+    let file = File::create(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+    components
+        .write_with_config(file, writer)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(files.len())
+}
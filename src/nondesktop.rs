@@ -0,0 +1,131 @@
+use crate::{
+    find::{textelement, GenOptions},
+    icon::findicons,
+    provides::autoprovides,
+    report::PkgReport,
+};
+use owo_colors::{OwoColorize, Stream::Stdout};
+use xmltree::{Element, XMLNode};
+
+fn provided(x: &Element, kind: &str) -> bool {
+    x.get_child("provides")
+        .map(|p| {
+            p.children
+                .iter()
+                .filter_map(|c| c.as_element())
+                .any(|c| c.name == kind)
+        })
+        .unwrap_or(false)
+}
+
+fn addon(x: &Element) -> Result<(), String> {
+    let extends = x
+        .children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .filter(|c| c.name == "extends")
+        .filter_map(|c| c.get_text())
+        .filter(|t| !t.trim().is_empty())
+        .count();
+    if extends == 0 {
+        return Err("addon does not extend any component".to_string());
+    }
+    Ok(())
+}
+
+fn font(x: &mut Element, pkg: &str) {
+    if !provided(x, "font") {
+        println!(
+            "{pkg}: {}",
+            "no fonts found".if_supports_color(Stdout, |x| x.bright_purple())
+        );
+    }
+
+    if x.get_child("icon").is_none() {
+        let mut i = textelement("icon", "font-x-generic");
+        i.attributes.insert("type".to_string(), "stock".to_string());
+        x.children.push(XMLNode::Element(i));
+    }
+}
+
+fn consoleapp(x: &Element) -> Result<(), String> {
+    if !provided(x, "binary") {
+        return Err("console application does not provide any binaries".to_string());
+    }
+    Ok(())
+}
+
+fn codec(x: &Element) -> Result<(), String> {
+    if !provided(x, "codec") {
+        return Err("codec does not provide any codecs".to_string());
+    }
+    Ok(())
+}
+
+fn firmware(x: &Element) -> Result<(), String> {
+    if !provided(x, "firmware") {
+        return Err("firmware does not provide any firmware files".to_string());
+    }
+    Ok(())
+}
+
+/// Caches an icon for the component from the hicolor theme of the store path, looked up by its stock icon
+/// name or by its id. Upstream icons are kept.
+fn icons(path: &str, x: &mut Element, pkg: &str, id: &str, opts: &GenOptions, report: &mut PkgReport) {
+    let stock = x
+        .children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .find(|c| c.name == "icon" && c.attributes.get("type").map(|t| t.as_str()) == Some("stock"))
+        .and_then(|c| c.get_text())
+        .map(|t| t.trim().to_string());
+    let name = match stock.or_else(|| {
+        x.get_child("id")
+            .and_then(|i| i.get_text())
+            .map(|t| t.trim().to_string())
+    }) {
+        Some(n) if !n.is_empty() => n,
+        _ => return,
+    };
+    let iout = opts.iconnames.file(&format!("{name}.png"), id, pkg);
+    for d in findicons(path, &name, &iout, opts.maxupscale, "output", pkg, report) {
+        x.children.push(XMLNode::Element(d));
+    }
+}
+
+/// Populates `<provides>` and applies the handling specific to the type of a component that is not
+/// paired with a desktop file. Binaries and D-Bus services are only added to the `primary` component
+/// of a package. Returns the component type.
+pub fn fixnondesktop(
+    path: &str,
+    x: &mut Element,
+    primary: bool,
+    pkg: &str,
+    id: &str,
+    opts: &GenOptions,
+    report: &mut PkgReport,
+) -> Result<String, String> {
+    let kind = x
+        .attributes
+        .get("type")
+        .cloned()
+        .unwrap_or_else(|| "generic".to_string());
+    autoprovides(path, x, None, primary);
+    match kind.as_str() {
+        "addon" => addon(x)?,
+        "font" => font(x, pkg),
+        "console-application" => consoleapp(x)?,
+        "codec" => codec(x)?,
+        "firmware" => firmware(x)?,
+        "desktop" | "desktop-application" => println!(
+            "{pkg}: {}",
+            "desktop application without a desktop file".if_supports_color(Stdout, |x| x.yellow())
+        ),
+        _ => (),
+    }
+
+    if kind != "firmware" && kind != "runtime" {
+        icons(path, x, pkg, id, opts, report);
+    }
+    Ok(kind)
+}
@@ -0,0 +1,307 @@
+use crate::{desktop::DesktopEntry, find::textelement, matcher::execbinary};
+use std::{
+    collections::HashSet,
+    fs,
+    os::unix::prelude::PermissionsExt,
+    path::{Path, PathBuf},
+};
+use xmltree::{Element, XMLNode};
+
+/// Recursively lists all files below `dir`, following symlinks, which store paths built with
+/// `symlinkJoin` consist of. Every directory is entered once, so symlink loops end the recursion.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    fn inner(dir: &Path, files: &mut Vec<PathBuf>, visited: &mut HashSet<PathBuf>) {
+        if !fs::canonicalize(dir).is_ok_and(|c| visited.insert(c)) {
+            return;
+        }
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let p = entry.path();
+                if p.is_dir() {
+                    inner(&p, files, visited);
+                } else if p.is_file() {
+                    files.push(p);
+                }
+            }
+        }
+    }
+    inner(dir, files, &mut HashSet::new());
+}
+
+/// Executables in `{path}/bin`, skipping hidden wrapper targets such as `.foo-wrapped`
+pub fn binaries(path: &str) -> Vec<String> {
+    let mut bins = vec![];
+    if let Ok(entries) = fs::read_dir(format!("{path}/bin")) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            if let Ok(m) = fs::metadata(entry.path()) {
+                if m.is_file() && m.permissions().mode() & 0o111 != 0 {
+                    bins.push(name);
+                }
+            }
+        }
+    }
+    bins.sort();
+    bins
+}
+
+fn be16(d: &[u8], o: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(d.get(o..o + 2)?.try_into().ok()?))
+}
+
+fn be32(d: &[u8], o: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(d.get(o..o + 4)?.try_into().ok()?))
+}
+
+/// Reads the full font name (name id 4) of the sfnt font whose offset table starts at `start`
+fn sfntname(d: &[u8], start: usize) -> Option<String> {
+    let numtables = be16(d, start + 4)? as usize;
+    let mut name = None;
+    for t in 0..numtables {
+        let rec = start + 12 + t * 16;
+        if d.get(rec..rec + 4)? == b"name" {
+            name = Some(be32(d, rec + 8)? as usize);
+            break;
+        }
+    }
+    let table = name?;
+    let count = be16(d, table + 2)? as usize;
+    let strings = table + be16(d, table + 4)? as usize;
+
+    let mut best: Option<(u8, String)> = None;
+    for r in 0..count {
+        let rec = table + 6 + r * 12;
+        let (platform, encoding, lang, nameid) = (
+            be16(d, rec)?,
+            be16(d, rec + 2)?,
+            be16(d, rec + 4)?,
+            be16(d, rec + 6)?,
+        );
+        if nameid != 4 {
+            continue;
+        }
+        let len = be16(d, rec + 8)? as usize;
+        let off = strings + be16(d, rec + 10)? as usize;
+        let raw = match d.get(off..off + len) {
+            Some(r) => r,
+            None => continue,
+        };
+        // Lower is better: English Windows names, then any Unicode name, then Mac Roman
+        let (rank, s) = match (platform, encoding) {
+            (3, _) | (0, _) => {
+                let units = raw
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect::<Vec<_>>();
+                let rank = if platform == 3 && lang == 0x409 { 0 } else { 1 };
+                (rank, String::from_utf16_lossy(&units))
+            }
+            (1, 0) => (2, raw.iter().map(|&c| c as char).collect()),
+            _ => continue,
+        };
+        if best.as_ref().map(|(b, _)| rank < *b).unwrap_or(true) {
+            best = Some((rank, s));
+        }
+    }
+    best.map(|(_, s)| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Full names of all fonts contained in a TrueType/OpenType font or collection
+pub fn fontnames(file: &Path) -> Vec<String> {
+    let d = match fs::read(file) {
+        Ok(d) => d,
+        Err(_) => return vec![],
+    };
+    if d.get(0..4) == Some(b"ttcf") {
+        let num = be32(&d, 8).unwrap_or(0) as usize;
+        (0..num)
+            .filter_map(|i| be32(&d, 12 + i * 4))
+            .filter_map(|o| sfntname(&d, o as usize))
+            .collect()
+    } else {
+        sfntname(&d, 0).into_iter().collect()
+    }
+}
+
+/// Full names of all fonts installed in `{path}/share/fonts`
+pub fn fonts(path: &str) -> Vec<String> {
+    let mut files = vec![];
+    walk(Path::new(&format!("{path}/share/fonts")), &mut files);
+    let mut names = files
+        .iter()
+        .filter(|f| {
+            matches!(
+                f.extension()
+                    .and_then(|e| e.to_str())
+                    .map(|e| e.to_lowercase())
+                    .as_deref(),
+                Some("ttf" | "otf" | "ttc" | "otc")
+            )
+        })
+        .flat_map(|f| fontnames(f))
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Firmware files in `{path}/lib/firmware`, relative to that directory as the kernel requests them
+pub fn firmware(path: &str) -> Vec<String> {
+    let base = PathBuf::from(format!("{path}/lib/firmware"));
+    let mut files = vec![];
+    walk(&base, &mut files);
+    let mut names = files
+        .iter()
+        .filter_map(|f| f.strip_prefix(&base).ok())
+        .map(|f| f.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Executables in `{path}/bin` started by `desktop` or by the desktop files the component names in
+/// `<launchable type="desktop-id">`
+pub fn launchedbinaries(path: &str, x: &Element, desktop: Option<&DesktopEntry>) -> Vec<String> {
+    let mut entries = desktop.cloned().into_iter().collect::<Vec<_>>();
+    for l in x.children.iter().filter_map(|c| c.as_element()) {
+        if l.name != "launchable"
+            || l.attributes.get("type").map(|t| t.as_str()) != Some("desktop-id")
+        {
+            continue;
+        }
+        if let Some(name) = l.get_text() {
+            entries.extend(
+                DesktopEntry::read(&format!("{path}/share/applications/{}", name.trim())).ok(),
+            );
+        }
+    }
+    let bins = binaries(path);
+    let mut launched = entries
+        .iter()
+        .filter_map(|d| d.get("Exec").and_then(|e| execbinary(&e)))
+        .filter(|b| bins.contains(b))
+        .collect::<Vec<_>>();
+    launched.sort();
+    launched.dedup();
+    launched
+}
+
+/// Media types listed in the `MimeType=` key of a desktop file
+pub fn mediatypes(desktop: &DesktopEntry) -> Vec<String> {
+    let mut types = desktop
+        .main()
+        .map(|g| g.list("MimeType"))
+        .unwrap_or_default();
+    types.sort();
+    types.dedup();
+    types
+}
+
+/// D-Bus service names activated by `{path}/share/dbus-1/{dir}`, taken from the `Name=` key of each
+/// `.service` file
+fn dbusnames(path: &str, dir: &str) -> Vec<String> {
+    let mut names = vec![];
+    if let Ok(entries) = fs::read_dir(format!("{path}/share/dbus-1/{dir}")) {
+        for entry in entries.flatten() {
+            let p = entry.path();
+            if p.extension().and_then(|e| e.to_str()) != Some("service") {
+                continue;
+            }
+            let name = DesktopEntry::read(&p.to_string_lossy())
+                .ok()
+                .and_then(|d| d.group("D-BUS Service").and_then(|g| g.get("Name")))
+                .map(|n| n.trim().to_string());
+            match name.or_else(|| p.file_stem().map(|n| n.to_string_lossy().to_string())) {
+                Some(n) if !n.is_empty() => names.push(n),
+                _ => (),
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Populates `<provides>` from the contents of the store path `path`: media types of the desktop
+/// file, fonts for font components and firmware for firmware components. Every binary and D-Bus
+/// service of the package only goes to its `primary` component, other components get the binaries
+/// their desktop files start. Entries already provided upstream are kept as they are.
+pub fn autoprovides(path: &str, x: &mut Element, desktop: Option<&DesktopEntry>, primary: bool) {
+    let kind = x.attributes.get("type").cloned().unwrap_or_default();
+
+    let bins = match primary {
+        true => binaries(path),
+        false => launchedbinaries(path, x, desktop),
+    };
+    addprovides(x, provideitems("binary", bins));
+    if let Some(d) = desktop {
+        addprovides(x, provideitems("mediatype", mediatypes(d)));
+    }
+    if primary {
+        for (dir, bus) in [("services", "user"), ("system-services", "system")] {
+            let items = dbusnames(path, dir)
+                .iter()
+                .map(|n| {
+                    let mut e = textelement("dbus", n);
+                    e.attributes.insert("type".to_string(), bus.to_string());
+                    e
+                })
+                .collect();
+            addprovides(x, items);
+        }
+    }
+
+    if kind == "font" {
+        addprovides(x, provideitems("font", fonts(path)));
+    }
+    if kind == "firmware" {
+        let items = firmware(path)
+            .iter()
+            .map(|f| {
+                let mut e = textelement("firmware", f);
+                e.attributes
+                    .insert("type".to_string(), "runtime".to_string());
+                e
+            })
+            .collect();
+        addprovides(x, items);
+    }
+}
+
+/// Adds `items` to the `<provides>` block of the component, skipping entries that are already provided
+pub fn addprovides(x: &mut Element, items: Vec<Element>) {
+    if items.is_empty() {
+        return;
+    }
+    if x.get_child("provides").is_none() {
+        x.children.push(XMLNode::Element(Element::new("provides")));
+    }
+    let provides = match x.get_mut_child("provides") {
+        Some(p) => p,
+        None => return,
+    };
+    for item in items {
+        let exists = provides
+            .children
+            .iter()
+            .filter_map(|c| c.as_element())
+            .any(|c| {
+                c.name == item.name
+                    && c.attributes.get("type") == item.attributes.get("type")
+                    && c.get_text().map(|t| t.trim().to_string())
+                        == item.get_text().map(|t| t.trim().to_string())
+            });
+        if !exists {
+            provides.children.push(XMLNode::Element(item));
+        }
+    }
+}
+
+/// Builds `<provides>` entries of type `kind` for `values`
+pub fn provideitems(kind: &str, values: Vec<String>) -> Vec<Element> {
+    values.iter().map(|v| textelement(kind, v)).collect()
+}
@@ -0,0 +1,220 @@
+use owo_colors::{OwoColorize, Stream::Stdout};
+use serde::{Deserialize, Serialize};
+use std::{any::Any, collections::BTreeMap, fs, path::Path};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    #[default]
+    Success,
+    /// Some components were written, but at least one step failed
+    Partial,
+    Failed,
+    BuildFailed,
+    NoMetadata,
+}
+
+/// An icon written to `output/icons`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CachedIcon {
+    pub sha256: String,
+    /// File or URL the icon was rendered from
+    #[serde(default)]
+    pub source: String,
+    /// Size of the source in pixels, `None` for vector images
+    #[serde(default)]
+    pub sourcesize: Option<u32>,
+}
+
+/// Outcome of processing a single package
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PkgReport {
+    pub status: Status,
+    /// Written components as `{pkg}::{id}`
+    pub components: Vec<String>,
+    pub errors: Vec<String>,
+    /// Quality problems that did not fail the package, such as upscaled or rejected icons
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Why each metainfo file was or was not paired with a desktop file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pairing: Vec<String>,
+    /// Translation percentage of every language found in the package's gettext catalogs
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub languages: BTreeMap<String, u32>,
+    /// Components whose text was changed while sanitizing, with the changes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sanitized: Vec<String>,
+    /// Cached icon files as `{size}x{size}/{file}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub icons: BTreeMap<String, CachedIcon>,
+    /// Time spent on the package including building it, used to balance sharded runs
+    #[serde(default)]
+    pub seconds: f64,
+}
+
+impl PkgReport {
+    /// Records an error for this package and prints it
+    pub fn error(&mut self, pkg: &str, msg: String) {
+        println!(
+            "{pkg}: {} {msg}",
+            "error:".if_supports_color(Stdout, |x| x.red())
+        );
+        self.errors.push(msg);
+    }
+
+    /// Records a warning for this package and prints it
+    pub fn warn(&mut self, pkg: &str, msg: String) {
+        println!(
+            "{pkg}: {} {msg}",
+            "warning:".if_supports_color(Stdout, |x| x.yellow())
+        );
+        self.warnings.push(msg);
+    }
+
+    /// Records the languages of a component, keeping the highest percentage of each language
+    pub fn addlanguages(&mut self, langs: BTreeMap<String, u32>) {
+        for (l, p) in langs {
+            let e = self.languages.entry(l).or_default();
+            *e = (*e).max(p);
+        }
+    }
+
+    /// Report for a package whose processing panicked
+    pub fn panicked(payload: Box<dyn Any + Send>) -> Self {
+        let msg = if let Some(s) = payload.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.to_string()
+        } else {
+            "unknown panic".to_string()
+        };
+        PkgReport {
+            status: Status::Failed,
+            components: vec![],
+            errors: vec![format!("panicked: {msg}")],
+            ..Default::default()
+        }
+    }
+
+    /// Derives the final status from the recorded components and errors
+    fn finish(&mut self) {
+        if self.status == Status::Success && !self.errors.is_empty() {
+            self.status = if self.components.is_empty() {
+                Status::Failed
+            } else {
+                Status::Partial
+            };
+        }
+    }
+}
+
+/// Results of a whole run, keyed by package attribute
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Report {
+    pub packages: BTreeMap<String, PkgReport>,
+    /// Package and hash of the last version of every cached icon file
+    #[serde(skip)]
+    icons: BTreeMap<String, (String, String)>,
+}
+
+impl Report {
+    /// Adds the report of a package. Cached icons that a different package wrote with other contents
+    /// before are recorded as errors, as that package's icon was overwritten.
+    pub fn add(&mut self, pkg: &str, mut r: PkgReport) {
+        let mut collisions = vec![];
+        for (file, icon) in &r.icons {
+            if let Some((other, hash)) = self.icons.get(file) {
+                if other != pkg && *hash != icon.sha256 {
+                    collisions.push(format!(
+                        "icon collision: {file} of {other} was overwritten with a different icon"
+                    ));
+                }
+            }
+            self.icons
+                .insert(file.clone(), (pkg.to_string(), icon.sha256.clone()));
+        }
+        for c in collisions {
+            r.error(pkg, c);
+        }
+        r.finish();
+        self.packages.insert(pkg.to_string(), r);
+    }
+
+    pub fn count(&self, status: Status) -> usize {
+        self.packages
+            .values()
+            .filter(|x| x.status == status)
+            .count()
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{path}: {e}"))?;
+        }
+        fs::write(path, data).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn summary(&self) {
+        let components = self
+            .packages
+            .values()
+            .map(|x| x.components.len())
+            .sum::<usize>();
+        println!(
+            "{} {} packages, {} components",
+            "Summary:".if_supports_color(Stdout, |x| x.bold()),
+            self.packages.len(),
+            components
+        );
+        println!(
+            "  {}: {}",
+            "succeeded".if_supports_color(Stdout, |x| x.green()),
+            self.count(Status::Success)
+        );
+        println!(
+            "  {}: {}",
+            "partially failed".if_supports_color(Stdout, |x| x.yellow()),
+            self.count(Status::Partial)
+        );
+        println!(
+            "  {}: {}",
+            "failed".if_supports_color(Stdout, |x| x.red()),
+            self.count(Status::Failed)
+        );
+        println!(
+            "  {}: {}",
+            "failed to build".if_supports_color(Stdout, |x| x.red()),
+            self.count(Status::BuildFailed)
+        );
+        println!(
+            "  {}: {}",
+            "no metadata".if_supports_color(Stdout, |x| x.bright_purple()),
+            self.count(Status::NoMetadata)
+        );
+
+        let mut langs: BTreeMap<&str, usize> = BTreeMap::new();
+        for l in self.packages.values().flat_map(|x| x.languages.keys()) {
+            *langs.entry(l).or_default() += 1;
+        }
+        let mut common = langs.iter().collect::<Vec<_>>();
+        common.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        println!(
+            "  {}: {} packages, {} languages{}{}",
+            "localized".if_supports_color(Stdout, |x| x.cyan()),
+            self.packages
+                .values()
+                .filter(|x| !x.languages.is_empty())
+                .count(),
+            langs.len(),
+            if common.is_empty() { "" } else { ", most common: " },
+            common
+                .iter()
+                .take(5)
+                .map(|(l, n)| format!("{l} ({n})"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    time::SystemTime,
+};
+
+/// Modification time, size and inode of every file below a directory, used to find the files a run wrote
+pub type Snapshot = BTreeMap<String, (Option<SystemTime>, u64, u64)>;
+
+fn walk(dir: &Path, snap: &mut Snapshot) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let p = entry.path();
+            match fs::metadata(&p) {
+                Ok(m) if m.is_dir() => walk(&p, snap),
+                Ok(m) => {
+                    snap.insert(
+                        p.to_string_lossy().to_string(),
+                        (m.modified().ok(), m.len(), m.ino()),
+                    );
+                }
+                Err(_) => (),
+            }
+        }
+    }
+}
+
+pub fn snapshot(dir: &str) -> Snapshot {
+    let mut snap = Snapshot::new();
+    walk(Path::new(dir), &mut snap);
+    snap
+}
+
+pub fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Files below `dir` that were created or changed since `before` was taken, with their SHA-256
+/// hashes. `skip` is never included.
+pub fn written(dir: &str, before: &Snapshot, skip: &str) -> BTreeMap<String, String> {
+    snapshot(dir)
+        .into_iter()
+        .filter(|(f, m)| f != skip && before.get(f) != Some(m))
+        .filter_map(|(f, _)| {
+            let data = fs::read(&f).ok()?;
+            Some((f, sha256(&data)))
+        })
+        .collect()
+}
+
+/// Differences between the files written by two runs
+pub fn compare(first: &BTreeMap<String, String>, second: &BTreeMap<String, String>) -> Vec<String> {
+    let mut diffs = vec![];
+    for (f, h) in first {
+        match second.get(f) {
+            Some(h2) if h2 == h => (),
+            Some(_) => diffs.push(format!("{f} differs between runs")),
+            None => diffs.push(format!("{f} was only written by the first run")),
+        }
+    }
+    for f in second.keys().filter(|f| !first.contains_key(*f)) {
+        diffs.push(format!("{f} was only written by the second run"));
+    }
+    diffs
+}
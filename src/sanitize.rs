@@ -0,0 +1,397 @@
+use std::{collections::BTreeMap, fmt};
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+use xmltree::{Element, ParseError, XMLNode};
+
+/// Elements allowed in descriptions
+const DESCRIPTIONMARKUP: [&str; 6] = ["p", "ul", "ol", "li", "em", "code"];
+
+/// Elements allowed in paragraphs and list items
+const INLINEMARKUP: [&str; 2] = ["em", "code"];
+
+/// Elements whose text is shown as a single line or paragraph, so whitespace is not significant
+const TEXTELEMENTS: [&str; 6] = ["p", "li", "name", "summary", "developer_name", "caption"];
+
+/// HTML tags that end up literally in text, usually from double-escaped upstream data
+const HTMLTAGS: [&str; 14] = [
+    "a", "b", "big", "br", "div", "font", "hr", "i", "small", "span", "strong", "tt", "u", "sup",
+];
+
+/// Elements whose surrounding whitespace separates words, like the space in `<em>a</em> <em>b</em>`
+const INLINETAGS: [&str; 14] = [
+    "em", "code", "a", "b", "big", "br", "font", "i", "small", "span", "strong", "tt", "u", "sup",
+];
+
+/// Named entities accepted besides the predefined XML entities, as upstream files often use HTML ones
+const HTMLENTITIES: [(&str, char); 16] = [
+    ("nbsp", '\u{a0}'),
+    ("copy", '©'),
+    ("reg", '®'),
+    ("trade", '™'),
+    ("mdash", '—'),
+    ("ndash", '–'),
+    ("hellip", '…'),
+    ("laquo", '«'),
+    ("raquo", '»'),
+    ("ldquo", '“'),
+    ("rdquo", '”'),
+    ("lsquo", '‘'),
+    ("rsquo", '’'),
+    ("bull", '•'),
+    ("middot", '·'),
+    ("deg", '°'),
+];
+
+/// Counts of the changes made while sanitizing a component, by kind
+#[derive(Debug, Default)]
+pub struct Changes(BTreeMap<&'static str, usize>);
+
+impl Changes {
+    fn add(&mut self, kind: &'static str, n: usize) {
+        if n > 0 {
+            *self.0.entry(kind).or_default() += n;
+        }
+    }
+
+    pub fn merge(&mut self, other: Changes) {
+        for (k, n) in other.0 {
+            self.add(k, n);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for Changes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self
+            .0
+            .iter()
+            .map(|(k, n)| format!("{k}: {n}"))
+            .collect::<Vec<_>>();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+fn entityname(s: &str) -> Option<&str> {
+    let end = s.find(';')?;
+    let name = &s[..end];
+    let valid = match name.strip_prefix('#') {
+        Some(n) => match n.strip_prefix('x').or_else(|| n.strip_prefix('X')) {
+            Some(h) => !h.is_empty() && h.chars().all(|c| c.is_ascii_hexdigit()),
+            None => !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()),
+        },
+        None => !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric()),
+    };
+    valid.then_some(name)
+}
+
+/// Makes raw XML parseable by replacing HTML entities with their characters and escaping `&` that
+/// does not start an entity
+pub fn fixentities(xml: &str, changes: &mut Changes) -> String {
+    let mut out = String::with_capacity(xml.len());
+    let mut rest = xml;
+    let mut fixed = 0;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        match entityname(rest) {
+            Some(n) if n.starts_with('#') || ["amp", "lt", "gt", "quot", "apos"].contains(&n) => {
+                out.push('&')
+            }
+            Some(n) => {
+                fixed += 1;
+                match HTMLENTITIES.iter().find(|(e, _)| *e == n) {
+                    Some((_, c)) => {
+                        out.push(*c);
+                        rest = &rest[n.len() + 1..];
+                    }
+                    None => out.push_str("&amp;"),
+                }
+            }
+            None => {
+                fixed += 1;
+                out.push_str("&amp;");
+            }
+        }
+    }
+    out.push_str(rest);
+    changes.add("invalid entities fixed", fixed);
+    out
+}
+
+fn element(name: xml::name::OwnedName, attributes: Vec<xml::attribute::OwnedAttribute>) -> Element {
+    let mut e = Element::new(&name.local_name);
+    e.prefix = name.prefix;
+    e.namespace = name.namespace;
+    for a in attributes {
+        e.attributes.insert(a.name.local_name, a.value);
+    }
+    e
+}
+
+fn build(reader: &mut EventReader<&[u8]>, mut e: Element) -> Result<Element, ParseError> {
+    loop {
+        match reader.next() {
+            Ok(XmlEvent::EndElement { .. }) => return Ok(e),
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => {
+                let c = build(reader, element(name, attributes))?;
+                e.children.push(XMLNode::Element(c));
+            }
+            Ok(XmlEvent::Characters(s) | XmlEvent::Whitespace(s) | XmlEvent::CData(s)) => {
+                e.children.push(XMLNode::Text(s))
+            }
+            Ok(XmlEvent::Comment(s)) => e.children.push(XMLNode::Comment(s)),
+            Ok(XmlEvent::ProcessingInstruction { name, data }) => {
+                e.children.push(XMLNode::ProcessingInstruction(name, data))
+            }
+            Ok(XmlEvent::StartDocument { .. } | XmlEvent::EndDocument) => {
+                return Err(ParseError::CannotParse)
+            }
+            Err(e) => return Err(ParseError::MalformedXml(e)),
+        }
+    }
+}
+
+fn inline(n: Option<&XMLNode>) -> bool {
+    matches!(n, Some(XMLNode::Element(e)) if INLINETAGS.contains(&e.name.as_str()))
+}
+
+/// Drops whitespace-only text, such as indentation, unless it is next to an inline element in
+/// human-readable text. Elsewhere tags like `font` are AppStream elements, not HTML.
+fn dropwhitespace(e: &mut Element, readable: bool) {
+    let readable = readable || e.name == "description" || TEXTELEMENTS.contains(&e.name.as_str());
+    let keep = (0..e.children.len())
+        .map(|i| match &e.children[i] {
+            XMLNode::Text(t) if t.trim().is_empty() => {
+                readable
+                    && (inline(i.checked_sub(1).and_then(|j| e.children.get(j)))
+                        || inline(e.children.get(i + 1)))
+            }
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    let mut keep = keep.into_iter();
+    e.children.retain(|_| keep.next().unwrap_or(true));
+    for c in e.children.iter_mut() {
+        if let XMLNode::Element(c) = c {
+            dropwhitespace(c, readable);
+        }
+    }
+}
+
+/// Parses a component like `Element::parse`, but keeps whitespace between inline elements, which
+/// `Element::parse` drops along with indentation
+pub fn parse(xml: &[u8]) -> Result<Element, ParseError> {
+    let config = ParserConfig::new()
+        .ignore_comments(false)
+        .whitespace_to_characters(true);
+    let mut reader = EventReader::new_with_config(xml, config);
+    loop {
+        match reader.next() {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => {
+                let mut x = build(&mut reader, element(name, attributes))?;
+                dropwhitespace(&mut x, false);
+                return Ok(x);
+            }
+            Ok(XmlEvent::EndDocument) => return Err(ParseError::CannotParse),
+            Ok(_) => (),
+            Err(e) => return Err(ParseError::MalformedXml(e)),
+        }
+    }
+}
+
+/// Decodes entities that survived parsing as literal text because upstream escaped them twice
+fn decodeentities(t: &str) -> (String, usize) {
+    let mut out = String::with_capacity(t.len());
+    let mut rest = t;
+    let mut n = 0;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let name = entityname(rest);
+        let c = name.and_then(|e| match e {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            e => match e.strip_prefix("#x").or_else(|| e.strip_prefix("#X")) {
+                Some(h) => u32::from_str_radix(h, 16).ok().and_then(char::from_u32),
+                None => match e.strip_prefix('#') {
+                    Some(d) => d.parse().ok().and_then(char::from_u32),
+                    None => HTMLENTITIES.iter().find(|(h, _)| *h == e).map(|(_, c)| *c),
+                },
+            },
+        });
+        match (name, c) {
+            (Some(name), Some(c)) => {
+                out.push(c);
+                rest = &rest[name.len() + 1..];
+                n += 1;
+            }
+            _ => out.push('&'),
+        }
+    }
+    out.push_str(rest);
+    (out, n)
+}
+
+/// Removes literal HTML tags such as `<br/>` or `</b>` from text
+fn striptags(t: &str) -> (String, usize) {
+    let mut out = String::with_capacity(t.len());
+    let mut rest = t;
+    let mut n = 0;
+    while let Some(i) = rest.find('<') {
+        out.push_str(&rest[..i]);
+        let tag = &rest[i + 1..];
+        let end = tag.find('>');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match end {
+            Some(e) if HTMLTAGS.contains(&name.as_str()) && !tag[..e].contains('<') => {
+                // A line break still separates words
+                if name == "br" {
+                    out.push(' ');
+                }
+                rest = &tag[e + 1..];
+                n += 1;
+            }
+            _ => {
+                out.push('<');
+                rest = tag;
+            }
+        }
+    }
+    out.push_str(rest);
+    (out, n)
+}
+
+fn zerowidth(c: char) -> bool {
+    // Zero-width (non-)joiners are kept, as they change how some scripts are rendered
+    matches!(c, '\u{200b}' | '\u{2060}' | '\u{feff}')
+}
+
+fn control(c: char) -> bool {
+    c.is_control() && !matches!(c, '\t' | '\n' | '\r')
+}
+
+/// Collapses whitespace runs in the text of `e` into single spaces and trims its ends. Non-breaking
+/// spaces are kept.
+fn normalizewhitespace(e: &mut Element) -> bool {
+    let last = e.children.len().saturating_sub(1);
+    let mut changed = false;
+    for (i, c) in e.children.iter_mut().enumerate() {
+        if let XMLNode::Text(t) = c {
+            let mut s = t.split_ascii_whitespace().collect::<Vec<_>>().join(" ");
+            if t.starts_with(|c: char| c.is_ascii_whitespace()) && i != 0 && !s.is_empty() {
+                s.insert(0, ' ');
+            }
+            if t.ends_with(|c: char| c.is_ascii_whitespace()) && i != last && !s.is_empty() {
+                s.push(' ');
+            }
+            if s.is_empty() && !t.is_empty() && i != 0 && i != last {
+                s.push(' ');
+            }
+            if *t != s {
+                *t = s;
+                changed = true;
+            }
+        }
+    }
+    e.children
+        .retain(|c| !matches!(c, XMLNode::Text(t) if t.is_empty()));
+    changed
+}
+
+/// Replaces child elements that are not allowed in `e` by their contents
+fn unwrap(e: &mut Element, allowed: &[&str], changes: &mut Changes) {
+    let mut children = vec![];
+    for c in std::mem::take(&mut e.children) {
+        match c {
+            XMLNode::Element(mut c) if !allowed.contains(&c.name.as_str()) => {
+                changes.add("markup removed", 1);
+                unwrap(&mut c, allowed, changes);
+                if c.name == "br" {
+                    children.push(XMLNode::Text(" ".to_string()));
+                }
+                children.append(&mut c.children);
+            }
+            XMLNode::Element(mut c) => {
+                unwrap(&mut c, allowed, changes);
+                children.push(XMLNode::Element(c));
+            }
+            c => children.push(c),
+        }
+    }
+    // Merge text split by removed elements
+    for c in children {
+        match (e.children.last_mut(), c) {
+            (Some(XMLNode::Text(a)), XMLNode::Text(b)) => a.push_str(&b),
+            (_, c) => e.children.push(c),
+        }
+    }
+}
+
+/// Removes invisible characters from all text. Entities and literal tags are only decoded and
+/// stripped in human-readable text, as URLs and other values may legitimately contain them.
+fn sanitizetext(e: &mut Element, readable: bool, changes: &mut Changes) {
+    let readable = readable || e.name == "description" || TEXTELEMENTS.contains(&e.name.as_str());
+    for c in e.children.iter_mut() {
+        match c {
+            XMLNode::Text(t) => {
+                let before = t.chars().count();
+                let mut s = t.chars().filter(|c| !zerowidth(*c)).collect::<String>();
+                changes.add("zero-width characters removed", before - s.chars().count());
+                let before = s.chars().count();
+                s = s.chars().filter(|c| !control(*c)).collect::<String>();
+                changes.add("control characters removed", before - s.chars().count());
+                if readable {
+                    let (decoded, n) = decodeentities(&s);
+                    changes.add("entities decoded", n);
+                    let (stripped, n) = striptags(&decoded);
+                    changes.add("markup removed", n);
+                    s = stripped;
+                }
+                *t = s;
+            }
+            XMLNode::Element(c) => sanitizetext(c, readable, changes),
+            _ => (),
+        }
+    }
+}
+
+fn sanitizeelement(e: &mut Element, changes: &mut Changes) {
+    if e.name == "description" {
+        unwrap(e, &DESCRIPTIONMARKUP, changes);
+    } else if e.name == "p" || e.name == "li" {
+        unwrap(e, &INLINEMARKUP, changes);
+    } else if TEXTELEMENTS.contains(&e.name.as_str()) {
+        unwrap(e, &[], changes);
+    }
+    if TEXTELEMENTS.contains(&e.name.as_str()) && normalizewhitespace(e) {
+        changes.add("whitespace normalized", 1);
+    }
+    for c in e.children.iter_mut() {
+        if let XMLNode::Element(c) = c {
+            sanitizeelement(c, changes);
+        }
+    }
+}
+
+/// Cleans up the text content of a component before it is written: removes zero-width and control
+/// characters, decodes double-escaped entities and strips stray markup in descriptions and
+/// single-line fields, and normalizes whitespace in paragraphs and single-line fields
+pub fn sanitize(x: &mut Element, changes: &mut Changes) {
+    sanitizetext(x, false, changes);
+    sanitizeelement(x, changes);
+}
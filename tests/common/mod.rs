@@ -0,0 +1,47 @@
+//! Helpers shared by the integration tests. Golden tests read every `{name}.in.xml` of a directory
+//! below `tests/data` and compare the result to `{name}.out.xml`.
+#![allow(dead_code)]
+
+use nixos_appstream_generator::{description::fixlangattrs, sanitize};
+use std::{env, fs, path::PathBuf};
+use xmltree::{Element, EmitterConfig};
+
+/// Parses a document like metainfo files are read
+pub fn parse(xml: &[u8]) -> Element {
+    let mut x = sanitize::parse(xml).unwrap();
+    fixlangattrs(&mut x);
+    x
+}
+
+pub fn render(x: &Element) -> String {
+    let mut out = vec![];
+    x.write_with_config(&mut out, EmitterConfig::new().perform_indent(true))
+        .unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Names of the golden cases in `dir`, sorted
+pub fn cases(dir: &str) -> Vec<String> {
+    let mut cases = fs::read_dir(dir)
+        .unwrap()
+        .flatten()
+        .filter_map(|e| {
+            e.file_name()
+                .to_str()
+                .and_then(|n| n.strip_suffix(".in.xml"))
+                .map(|n| n.to_string())
+        })
+        .collect::<Vec<_>>();
+    cases.sort();
+    assert!(!cases.is_empty(), "no cases in {dir}");
+    cases
+}
+
+/// Creates an empty scratch directory for the test `name`, so tests never write into the crate.
+/// The caller removes it when done.
+pub fn tempdir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("appstream-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::{cases, parse, render};
+use nixos_appstream_generator::description::normalize;
+use std::fs;
+
+const DIR: &str = "tests/data/description";
+
+/// Normalizes every golden case. Documents are compared after parsing, so the indentation of the
+/// expected files does not matter.
+#[test]
+fn golden() {
+    for case in cases(DIR) {
+        let mut x = parse(&fs::read(format!("{DIR}/{case}.in.xml")).unwrap());
+        normalize(&mut x);
+        let actual = render(&x);
+        let expected = parse(&fs::read(format!("{DIR}/{case}.out.xml")).unwrap());
+        assert!(
+            parse(actual.as_bytes()) == expected,
+            "{case}: got\n{actual}\nexpected\n{}",
+            render(&expected)
+        );
+        assert!(
+            !actual.contains(" lang="),
+            "{case}: lost xml: prefix\n{actual}"
+        );
+    }
+}
+
+#[test]
+fn idempotent() {
+    let mut x = parse(&fs::read(format!("{DIR}/lists.in.xml")).unwrap());
+    normalize(&mut x);
+    let once = x.clone();
+    normalize(&mut x);
+    assert!(x == once);
+}
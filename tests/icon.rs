@@ -0,0 +1,101 @@
+mod common;
+
+use common::tempdir;
+use image::{Rgba, RgbaImage};
+use nixos_appstream_generator::{icon::findicons, report::PkgReport};
+use std::fs;
+
+/// A theme whose 128px icon and scalable icon are wide falls back to the square 256px icon for both
+/// sizes. The wide SVG has to be rejected before rendering, as rendering it would center it on a
+/// square canvas.
+#[test]
+fn wrongaspect() {
+    let tmp = tempdir("icon");
+    let theme = tmp.join("store/share/icons/hicolor");
+    for dir in ["128x128/apps", "256x256/apps", "scalable/apps"] {
+        fs::create_dir_all(theme.join(dir)).unwrap();
+    }
+    let output = tmp.join("output");
+    for dir in ["icons/128x128", "icons/64x64"] {
+        fs::create_dir_all(output.join(dir)).unwrap();
+    }
+
+    let opaque = Rgba([0, 128, 255, 255]);
+    RgbaImage::from_pixel(128, 64, opaque)
+        .save(theme.join("128x128/apps/app.png"))
+        .unwrap();
+    RgbaImage::from_pixel(256, 256, opaque)
+        .save(theme.join("256x256/apps/app.png"))
+        .unwrap();
+    fs::write(
+        theme.join("scalable/apps/app.svg"),
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 200 100"/>"#,
+    )
+    .unwrap();
+
+    let mut report = PkgReport::default();
+    let store = tmp.join("store").display().to_string();
+    let output = output.display().to_string();
+    let icons = findicons(&store, "app", "app.png", 2.0, &output, "app", &mut report);
+    let dims = ["128x128", "64x64"]
+        .map(|s| image::image_dimensions(format!("{output}/icons/{s}/app.png")));
+    fs::remove_dir_all(&tmp).unwrap();
+
+    assert_eq!(icons.len(), 2, "{report:?}");
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    for file in ["128x128/app.png", "64x64/app.png"] {
+        let source = &report.icons[file].source;
+        assert!(
+            source.ends_with("256x256/apps/app.png"),
+            "{file} from {source}"
+        );
+    }
+    assert_eq!(dims.map(|d| d.unwrap()), [(128, 128), (64, 64)]);
+    for bad in [
+        "128x128/apps/app.png: wrong aspect ratio 128x64",
+        "app.svg: wrong aspect ratio 200x100",
+    ] {
+        assert!(
+            report.warnings.iter().any(|w| w.contains(bad)),
+            "no warning for {bad}: {:?}",
+            report.warnings
+        );
+    }
+}
+
+/// Two components of a package whose icons are cached under the same name: the second one
+/// overwrites the first, which is an error, while caching the same icon again is not
+#[test]
+fn samepackage() {
+    let tmp = tempdir("icon-samepackage");
+    let theme = tmp.join("store/share/icons/hicolor/256x256/apps");
+    fs::create_dir_all(&theme).unwrap();
+    let output = tmp.join("output");
+    for dir in ["icons/128x128", "icons/64x64"] {
+        fs::create_dir_all(output.join(dir)).unwrap();
+    }
+    for (name, color) in [("app", [0, 128, 255, 255]), ("tool", [255, 128, 0, 255])] {
+        RgbaImage::from_pixel(256, 256, Rgba(color))
+            .save(theme.join(format!("{name}.png")))
+            .unwrap();
+    }
+
+    let store = tmp.join("store").display().to_string();
+    let output = output.display().to_string();
+    let mut report = PkgReport::default();
+    findicons(&store, "app", "app.png", 2.0, &output, "app", &mut report);
+    findicons(&store, "app", "app.png", 2.0, &output, "app", &mut report);
+    let again = report.errors.clone();
+    findicons(&store, "tool", "app.png", 2.0, &output, "app", &mut report);
+    fs::remove_dir_all(&tmp).unwrap();
+
+    assert!(again.is_empty(), "{again:?}");
+    assert_eq!(report.errors.len(), 2, "{:?}", report.errors);
+    for (error, size) in report.errors.iter().zip(["128x128", "64x64"]) {
+        assert!(
+            error.starts_with(&format!("icon collision: {size}/app.png from ")),
+            "{error}"
+        );
+        assert!(error.ends_with("256x256/apps/tool.png"), "{error}");
+    }
+}
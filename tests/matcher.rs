@@ -0,0 +1,44 @@
+mod common;
+
+use common::tempdir;
+use nixos_appstream_generator::{desktop::DesktopEntry, matcher::resolve};
+use std::{collections::HashMap, fs};
+
+/// A desktop application whose metainfo file only parses once its HTML entities are fixed is still
+/// paired with its desktop file, as it is read the same way the component is generated
+#[test]
+fn htmlentities() {
+    let dir = tempdir("matcher");
+    let meta = dir.join("org.example.App.metainfo.xml");
+    fs::write(
+        &meta,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<component type="desktop-application">
+  <id>org.example.App</id>
+  <name>App</name>
+  <description><p>Edits&nbsp;files &mdash; quickly</p></description>
+</component>
+"#,
+    )
+    .unwrap();
+    let desktop = dir.join("org.example.App.desktop");
+    fs::write(
+        &desktop,
+        "[Desktop Entry]\nType=Application\nName=App\nExec=app\n",
+    )
+    .unwrap();
+
+    let meta = meta.display().to_string();
+    let entries = vec![DesktopEntry::read(&desktop.display().to_string()).unwrap()];
+    let resolved = resolve(&[meta], &entries, "app", &HashMap::new());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(resolved.len(), 1);
+    assert!(resolved[0].isdesktop, "{}", resolved[0].explanation);
+    assert_eq!(
+        resolved[0].desktop.as_ref().map(|d| d.filename()),
+        Some("org.example.App.desktop"),
+        "{}",
+        resolved[0].explanation
+    );
+}
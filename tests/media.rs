@@ -0,0 +1,60 @@
+mod common;
+
+use common::{cases, parse, render, tempdir};
+use image::{Rgba, RgbaImage};
+use nixos_appstream_generator::media::mirrorscreenshots;
+use std::fs;
+
+const DIR: &str = "tests/data/media";
+const MEDIAURL: &str = "https://media.example";
+
+/// Mirrors the screenshots of every golden case from local files. `@DIR@` stands for the directory
+/// of the source screenshots.
+#[test]
+fn golden() {
+    let tmp = tempdir("media");
+    for (name, color) in [
+        ("shot.png", [255, 0, 0, 255]),
+        ("shot-de.png", [0, 0, 255, 255]),
+    ] {
+        RgbaImage::from_pixel(300, 200, Rgba(color))
+            .save(tmp.join(name))
+            .unwrap();
+    }
+    let url = format!("file://{}", tmp.display());
+    let (output, cache) = (tmp.join("output"), tmp.join("cache"));
+
+    let mut results = vec![];
+    for case in cases(DIR) {
+        let read = |ext: &str| {
+            let f = fs::read_to_string(format!("{DIR}/{case}.{ext}")).unwrap();
+            parse(f.replace("@DIR@", &url).as_bytes())
+        };
+        let mut x = read("in.xml");
+        mirrorscreenshots(
+            &mut x,
+            "org.example.App",
+            "app",
+            MEDIAURL,
+            &output.display().to_string(),
+            &cache.display().to_string(),
+        );
+        let expected = read("out.xml");
+        let mirrored = output.join("media/org.example.App/screenshots/image-1_orig.png");
+        results.push((case, render(&x), expected, mirrored.is_file()));
+    }
+    fs::remove_dir_all(&tmp).unwrap();
+
+    for (case, actual, expected, mirrored) in results {
+        assert!(mirrored, "{case}: nothing written to the output directory");
+        assert!(
+            parse(actual.as_bytes()) == expected,
+            "{case}: got\n{actual}\nexpected\n{}",
+            render(&expected)
+        );
+        assert!(
+            !actual.contains(" lang="),
+            "{case}: lost xml: prefix\n{actual}"
+        );
+    }
+}
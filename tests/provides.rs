@@ -0,0 +1,71 @@
+mod common;
+
+use common::tempdir;
+use nixos_appstream_generator::{desktop::DesktopEntry, provides::autoprovides};
+use std::{fs, os::unix::prelude::PermissionsExt, path::Path};
+use xmltree::Element;
+
+fn write(path: &Path, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+fn provided(x: &Element, kind: &str) -> Vec<String> {
+    x.get_child("provides")
+        .map(|p| {
+            p.children
+                .iter()
+                .filter_map(|c| c.as_element())
+                .filter(|c| c.name == kind)
+                .filter_map(|c| c.get_text().map(|t| t.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A package with an editor and a separate tool: the editor is the primary component and gets every
+/// binary and D-Bus service, the tool only gets what its desktop files start
+#[test]
+fn twocomponents() {
+    let store = tempdir("provides");
+    for bin in ["app", "app-helper", "tool"] {
+        let p = store.join("bin").join(bin);
+        write(&p, "#!/bin/sh\n");
+        fs::set_permissions(&p, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let apps = store.join("share/applications");
+    let entry = |exec: &str| format!("[Desktop Entry]\nType=Application\nName=App\nExec={exec}\n");
+    write(&apps.join("app.desktop"), &entry("app %U"));
+    write(
+        &apps.join("tool.desktop"),
+        &entry("env GDK_BACKEND=x11 tool --new-window"),
+    );
+    write(&apps.join("helper.desktop"), &entry("app-helper"));
+    write(
+        &store.join("share/dbus-1/services/org.example.App.service"),
+        "[D-BUS Service]\nName=org.example.App\nExec=app --gapplication-service\n",
+    );
+
+    let path = store.display().to_string();
+    let desktop = |name: &str| DesktopEntry::read(&apps.join(name).display().to_string()).unwrap();
+    let mut app = Element::parse(
+        br#"<component type="desktop-application"><id>org.example.App</id></component>"#.as_slice(),
+    )
+    .unwrap();
+    let mut tool = Element::parse(
+        br#"<component type="desktop-application">
+              <id>org.example.Tool</id>
+              <launchable type="desktop-id">helper.desktop</launchable>
+            </component>"#
+            .as_slice(),
+    )
+    .unwrap();
+    autoprovides(&path, &mut app, Some(&desktop("app.desktop")), true);
+    autoprovides(&path, &mut tool, Some(&desktop("tool.desktop")), false);
+    fs::remove_dir_all(&store).unwrap();
+
+    assert_eq!(provided(&app, "binary"), ["app", "app-helper", "tool"]);
+    assert_eq!(provided(&app, "dbus"), ["org.example.App"]);
+    assert_eq!(provided(&tool, "binary"), ["app-helper", "tool"]);
+    assert!(provided(&tool, "dbus").is_empty());
+}
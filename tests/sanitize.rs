@@ -0,0 +1,38 @@
+mod common;
+
+use common::{cases, parse, render};
+use nixos_appstream_generator::sanitize::{fixentities, sanitize, Changes};
+use std::fs;
+
+const DIR: &str = "tests/data/sanitize";
+
+/// Sanitizes every golden case after fixing its entities
+#[test]
+fn golden() {
+    for case in cases(DIR) {
+        let mut changes = Changes::default();
+        let raw = fs::read_to_string(format!("{DIR}/{case}.in.xml")).unwrap();
+        let mut x = parse(fixentities(&raw, &mut changes).as_bytes());
+        sanitize(&mut x, &mut changes);
+        let expected = parse(&fs::read(format!("{DIR}/{case}.out.xml")).unwrap());
+        assert!(
+            x == expected,
+            "{case}: got\n{}\nexpected\n{}",
+            render(&x),
+            render(&expected)
+        );
+    }
+}
+
+#[test]
+fn idempotent() {
+    let mut changes = Changes::default();
+    let raw = fs::read_to_string(format!("{DIR}/inline.in.xml")).unwrap();
+    let mut x = parse(fixentities(&raw, &mut changes).as_bytes());
+    sanitize(&mut x, &mut changes);
+    let once = x.clone();
+    let mut again = Changes::default();
+    sanitize(&mut x, &mut again);
+    assert!(x == once);
+    assert!(again.is_empty(), "changed again: {again}");
+}